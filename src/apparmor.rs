@@ -0,0 +1,28 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+/// Confine the calling process with the AppArmor profile on its next execve.
+pub fn apply_profile(profile: &str) {
+	if profile.is_empty() {
+		return;
+	}
+	debug!("Apply AppArmor profile {}", profile);
+
+	// Kernels with LSM stacking provide a separate attribute for AppArmor
+	let attr = if Path::new("/proc/self/attr/apparmor/exec").exists() {
+		"/proc/self/attr/apparmor/exec"
+	} else {
+		"/proc/self/attr/exec"
+	};
+	OpenOptions::new()
+		.write(true)
+		.open(attr)
+		.and_then(|mut file| file.write_all(format!("exec {}", profile).as_bytes()))
+		.unwrap_or_else(|err| {
+			panic!(
+				"Could not apply AppArmor profile {}. Is AppArmor enabled? {}",
+				profile, err
+			)
+		});
+}
@@ -0,0 +1,66 @@
+use capctl::caps::{ambient, bounding, Cap, CapSet, CapState};
+use capctl::prctl;
+use oci_spec::runtime::{Capabilities, LinuxCapabilities};
+
+fn to_cap_set(capabilities: &Option<Capabilities>) -> CapSet {
+	let mut cap_set = CapSet::empty();
+	for capability in capabilities.iter().flatten() {
+		// oci-spec displays capabilities without the CAP_ prefix
+		match format!("CAP_{}", capability).parse::<Cap>() {
+			Ok(cap) if cap.is_supported() => cap_set.add(cap),
+			_ => warn!(
+				"Capability CAP_{} is not supported by the kernel, ignoring it!",
+				capability
+			),
+		}
+	}
+	cap_set
+}
+
+/// Drop all capabilities from the bounding set that are not requested in the spec.
+/// This has to happen while the process still has CAP_SETPCAP, i.e. before switching the user.
+pub fn apply_bounding_set(capabilities: &LinuxCapabilities) {
+	let bounding_set = to_cap_set(capabilities.bounding());
+	for cap in Cap::iter().filter(|cap| cap.is_supported() && !bounding_set.has(*cap)) {
+		bounding::drop(cap)
+			.unwrap_or_else(|err| panic!("Could not drop {:?} from bounding set: {}", cap, err));
+	}
+}
+
+/// Keep the permitted capabilities when switching from root to another user.
+/// Has to be reset with `clear_keep_caps` after the user switch.
+pub fn set_keep_caps() {
+	prctl::set_keepcaps(true).expect("Could not set PR_SET_KEEPCAPS!");
+}
+
+pub fn clear_keep_caps() {
+	prctl::set_keepcaps(false).expect("Could not clear PR_SET_KEEPCAPS!");
+}
+
+/// Clear all capabilities of the calling process.
+pub fn drop_capabilities() {
+	CapState::empty()
+		.set_current()
+		.expect("Could not drop process capabilities!");
+	ambient::clear().expect("Could not clear ambient capabilities!");
+}
+
+/// Set the effective, permitted, inheritable and ambient capabilities of the spec.
+pub fn apply_capabilities(capabilities: &LinuxCapabilities) {
+	let mut state = CapState::empty();
+	state.effective = to_cap_set(capabilities.effective());
+	state.permitted = to_cap_set(capabilities.permitted());
+	state.inheritable = to_cap_set(capabilities.inheritable());
+	debug!("Applying capabilities {:?}", state);
+	state
+		.set_current()
+		.expect("Could not set process capabilities!");
+
+	// Ambient capabilities can only be raised if they are permitted and inheritable
+	ambient::clear().expect("Could not clear ambient capabilities!");
+	for cap in to_cap_set(capabilities.ambient()).iter() {
+		if let Err(err) = ambient::raise(cap) {
+			warn!("Could not raise ambient capability {:?}: {}", cap, err);
+		}
+	}
+}
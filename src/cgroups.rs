@@ -0,0 +1,345 @@
+use oci_spec::runtime;
+use path_clean::PathClean;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Checks whether the unified cgroup v2 hierarchy is mounted at `/sys/fs/cgroup`.
+pub fn is_cgroup_v2() -> bool {
+	Path::new(CGROUP_ROOT).join("cgroup.controllers").exists()
+}
+
+/// Determine the absolute path of the container cgroup from `linux.cgroupsPath`.
+/// Without a configured path, the cgroup is created at `/runh/<id>`.
+pub fn get_cgroup_path(spec: &runtime::Spec, id: &str) -> PathBuf {
+	let cgroups_path = spec
+		.linux()
+		.as_ref()
+		.and_then(|linux| linux.cgroups_path().clone())
+		.unwrap_or_else(|| PathBuf::from("/runh").join(id));
+	// Cleaning the path as absolute path ensures it cannot escape the cgroup root
+	let clean_path = PathBuf::from("/").join(cgroups_path).clean();
+	PathBuf::from(CGROUP_ROOT).join(clean_path.strip_prefix("/").unwrap())
+}
+
+/// Checks whether the cgroup exists or can be created by the current user. Unprivileged
+/// users can only create cgroups in subtrees that were delegated to them.
+pub fn can_create_cgroup(cgroup: &Path) -> bool {
+	let Some(existing) = cgroup.ancestors().find(|ancestor| ancestor.exists()) else {
+		return false;
+	};
+	nix::unistd::access(existing, nix::unistd::AccessFlags::W_OK).is_ok()
+		&& nix::unistd::access(
+			&existing.join("cgroup.procs"),
+			nix::unistd::AccessFlags::W_OK,
+		)
+		.is_ok()
+}
+
+fn write_value(cgroup: &Path, file: &str, value: &str) {
+	let path = cgroup.join(file);
+	debug!("Writing {} to cgroup file {:?}", value, path);
+	fs::write(&path, value)
+		.unwrap_or_else(|err| panic!("Could not write {} to {:?}: {}", value, path, err));
+}
+
+/// Writes a resource limit, but only warns if the corresponding controller is not available.
+fn write_resource(cgroup: &Path, file: &str, value: &str) {
+	if cgroup.join(file).exists() {
+		write_value(cgroup, file, value);
+	} else {
+		warn!(
+			"Cgroup file {} does not exist, the corresponding controller is probably not available. Ignoring value {}...",
+			file, value
+		);
+	}
+}
+
+fn enable_controllers(cgroup: &Path) {
+	let mut current = PathBuf::from(CGROUP_ROOT);
+	for component in cgroup.strip_prefix(CGROUP_ROOT).unwrap().components() {
+		let controllers =
+			fs::read_to_string(current.join("cgroup.controllers")).unwrap_or_default();
+		for controller in controllers.split_whitespace() {
+			if let Err(err) = fs::write(
+				current.join("cgroup.subtree_control"),
+				format!("+{controller}"),
+			) {
+				debug!(
+					"Could not enable controller {} in {:?}: {}",
+					controller, current, err
+				);
+			}
+		}
+		current.push(component);
+		if !current.exists() {
+			fs::create_dir(&current)
+				.unwrap_or_else(|err| panic!("Could not create cgroup at {:?}: {}", current, err));
+		}
+	}
+}
+
+fn limit_to_string(limit: i64) -> String {
+	if limit < 0 {
+		String::from("max")
+	} else {
+		limit.to_string()
+	}
+}
+
+fn apply_memory(cgroup: &Path, memory: &runtime::LinuxMemory) {
+	if let Some(limit) = memory.limit() {
+		write_resource(cgroup, "memory.max", &limit_to_string(limit));
+	}
+	if let Some(reservation) = memory.reservation() {
+		write_resource(cgroup, "memory.low", &limit_to_string(reservation));
+	}
+	// In cgroup v1, the swap limit includes the memory limit, in v2 it does not
+	if let Some(swap) = memory.swap() {
+		let swap_limit = match memory.limit() {
+			Some(limit) if swap > 0 && limit > 0 => {
+				if swap < limit {
+					panic!(
+						"Memory+swap limit {} must not be lower than the memory limit {}!",
+						swap, limit
+					);
+				}
+				swap - limit
+			}
+			_ => swap,
+		};
+		write_resource(cgroup, "memory.swap.max", &limit_to_string(swap_limit));
+	}
+}
+
+fn apply_cpu(cgroup: &Path, cpu: &runtime::LinuxCpu) {
+	if let Some(shares) = cpu.shares() {
+		if shares > 0 {
+			// Conversion from cpu.shares [2-262144] to cpu.weight [1-10000]
+			let weight = 1 + ((shares.clamp(2, 262144) - 2) * 9999) / 262142;
+			write_resource(cgroup, "cpu.weight", &weight.to_string());
+		}
+	}
+	if cpu.quota().is_some() || cpu.period().is_some() {
+		let quota = cpu
+			.quota()
+			.filter(|quota| *quota > 0)
+			.map_or_else(|| String::from("max"), |quota| quota.to_string());
+		let period = cpu.period().unwrap_or(100000);
+		write_resource(cgroup, "cpu.max", &format!("{quota} {period}"));
+	}
+	if let Some(idle) = cpu.idle() {
+		write_resource(cgroup, "cpu.idle", &idle.to_string());
+	}
+	if let Some(cpus) = cpu.cpus() {
+		write_resource(cgroup, "cpuset.cpus", cpus);
+	}
+	if let Some(mems) = cpu.mems() {
+		write_resource(cgroup, "cpuset.mems", mems);
+	}
+}
+
+fn apply_block_io(cgroup: &Path, block_io: &runtime::LinuxBlockIo) {
+	// Conversion from blkio.weight [10-1000] to io.weight [1-10000]
+	let convert_weight = |weight: u16| 1 + ((weight.clamp(10, 1000) as u64 - 10) * 9999) / 990;
+
+	if let Some(weight) = block_io.weight() {
+		write_resource(
+			cgroup,
+			"io.weight",
+			&format!("default {}", convert_weight(weight)),
+		);
+	}
+	if let Some(weight_devices) = block_io.weight_device() {
+		for device in weight_devices {
+			if let Some(weight) = device.weight() {
+				write_resource(
+					cgroup,
+					"io.weight",
+					&format!(
+						"{}:{} {}",
+						device.major(),
+						device.minor(),
+						convert_weight(weight)
+					),
+				);
+			}
+		}
+	}
+
+	let throttle_limits = [
+		("rbps", block_io.throttle_read_bps_device()),
+		("wbps", block_io.throttle_write_bps_device()),
+		("riops", block_io.throttle_read_iops_device()),
+		("wiops", block_io.throttle_write_iops_device()),
+	];
+	for (key, devices) in throttle_limits {
+		for device in devices.iter().flatten() {
+			write_resource(
+				cgroup,
+				"io.max",
+				&format!(
+					"{}:{} {}={}",
+					device.major(),
+					device.minor(),
+					key,
+					device.rate()
+				),
+			);
+		}
+	}
+}
+
+/// Apply the resource limits of the container spec to the given cgroup.
+pub fn apply_resources(cgroup: &Path, resources: &runtime::LinuxResources) {
+	if let Some(memory) = resources.memory() {
+		apply_memory(cgroup, memory);
+	}
+	if let Some(cpu) = resources.cpu() {
+		apply_cpu(cgroup, cpu);
+	}
+	if let Some(pids) = resources.pids() {
+		let limit = if pids.limit() > 0 { pids.limit() } else { -1 };
+		write_resource(cgroup, "pids.max", &limit_to_string(limit));
+	}
+	if let Some(block_io) = resources.block_io() {
+		apply_block_io(cgroup, block_io);
+	}
+	for hugepage_limit in resources.hugepage_limits().iter().flatten() {
+		write_resource(
+			cgroup,
+			&format!("hugetlb.{}.max", hugepage_limit.page_size()),
+			&limit_to_string(hugepage_limit.limit()),
+		);
+	}
+	if let Some(devices) = resources
+		.devices()
+		.as_ref()
+		.filter(|devices| !devices.is_empty())
+	{
+		warn!(
+			"Device cgroup rules require eBPF in cgroup v2 and are not enforced! Ignoring {} rule(s).",
+			devices.len()
+		);
+	}
+	for (key, value) in resources.unified().iter().flatten() {
+		if key.contains('/') {
+			panic!("Invalid key {} in unified cgroup resources!", key);
+		}
+		write_value(cgroup, key, value);
+	}
+}
+
+/// Create the container cgroup, apply all resource limits and move the process with the given PID into it.
+pub fn create_cgroup(cgroup: &Path, resources: Option<&runtime::LinuxResources>, pid: i32) {
+	debug!("Creating cgroup at {:?} for process {}", cgroup, pid);
+	enable_controllers(cgroup);
+
+	if let Some(resources) = resources {
+		apply_resources(cgroup, resources);
+	}
+
+	join_cgroup(cgroup, pid);
+}
+
+/// Move the process with the given PID into an existing cgroup.
+pub fn join_cgroup(cgroup: &Path, pid: i32) {
+	write_value(cgroup, "cgroup.procs", &pid.to_string());
+}
+
+/// Remove the container cgroup. This only succeeds once all container processes have exited.
+pub fn remove_cgroup(cgroup: &Path) {
+	if !cgroup.exists() {
+		return;
+	}
+
+	debug!("Removing cgroup at {:?}", cgroup);
+	let mut retries = 10;
+	while let Err(err) = fs::remove_dir(cgroup) {
+		retries -= 1;
+		if retries == 0 {
+			warn!("Could not remove cgroup at {:?}: {}", cgroup, err);
+			return;
+		}
+		std::thread::sleep(Duration::from_millis(100));
+	}
+}
+
+/// Collect the PIDs of all processes in the cgroup and its descendants.
+pub fn get_pids(cgroup: &Path) -> Vec<i32> {
+	let mut pids: Vec<i32> = fs::read_to_string(cgroup.join("cgroup.procs"))
+		.unwrap_or_else(|err| panic!("Could not read processes of cgroup {:?}: {}", cgroup, err))
+		.lines()
+		.filter_map(|line| line.trim().parse().ok())
+		.collect();
+
+	for entry in fs::read_dir(cgroup)
+		.unwrap_or_else(|err| panic!("Could not read cgroup directory {:?}: {}", cgroup, err))
+		.flatten()
+	{
+		if entry.file_type().map(|typ| typ.is_dir()).unwrap_or(false) {
+			pids.append(&mut get_pids(&entry.path()));
+		}
+	}
+	pids
+}
+
+/// Checks whether the cgroup freezer is available for the given cgroup.
+pub fn has_freezer(cgroup: &Path) -> bool {
+	cgroup.join("cgroup.freeze").exists()
+}
+
+/// Freeze or thaw all processes in the cgroup and wait until the kernel reports the new state.
+pub fn set_frozen(cgroup: &Path, frozen: bool) {
+	write_value(cgroup, "cgroup.freeze", if frozen { "1" } else { "0" });
+
+	let expected = if frozen { "frozen 1" } else { "frozen 0" };
+	for _ in 0..100 {
+		if fs::read_to_string(cgroup.join("cgroup.events"))
+			.map(|events| events.lines().any(|line| line == expected))
+			.unwrap_or(true)
+		{
+			return;
+		}
+		std::thread::sleep(Duration::from_millis(10));
+	}
+	warn!(
+		"Cgroup {:?} did not reach the state \"{}\" in time!",
+		cgroup, expected
+	);
+}
+
+/// Checks whether the processes of the cgroup are currently frozen.
+pub fn is_frozen(cgroup: &Path) -> bool {
+	fs::read_to_string(cgroup.join("cgroup.events"))
+		.map(|events| events.lines().any(|line| line == "frozen 1"))
+		.unwrap_or(false)
+}
+
+/// Kill all processes in the cgroup at once. Returns false if `cgroup.kill` is not supported (Linux < 5.14).
+pub fn kill_all(cgroup: &Path) -> bool {
+	if cgroup.join("cgroup.kill").exists() {
+		write_value(cgroup, "cgroup.kill", "1");
+		true
+	} else {
+		false
+	}
+}
+
+/// Remember the cgroup of a container, so that later runh calls do not depend on the cgroup driver.
+pub fn save_cgroup_path(container_dir: &Path, cgroup: &Path) {
+	fs::write(
+		container_dir.join("cgroup"),
+		cgroup.as_os_str().as_encoded_bytes(),
+	)
+	.expect("Could not write cgroup path to container dir!");
+}
+
+/// Read the cgroup of a container, if one was created by runh create.
+pub fn load_cgroup_path(container_dir: &Path) -> Option<PathBuf> {
+	fs::read_to_string(container_dir.join("cgroup"))
+		.ok()
+		.map(PathBuf::from)
+}
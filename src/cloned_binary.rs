@@ -0,0 +1,85 @@
+use std::fs::{File, OpenOptions};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Command;
+
+// This is analogous to runc's libcontainer/nsenter/cloned_binary.c
+// A container process with access to /proc/<pid>/exe of runh init could otherwise
+// overwrite the runh binary on the host (CVE-2019-5736). Therefore, runh init is started
+// from a sealed in-memory copy of the binary, which cannot be modified.
+
+const MEMFD_NAME: &str = "runh_cloned:/proc/self/exe";
+const RUNH_MEMFD_SEALS: libc::c_int =
+	libc::F_SEAL_SEAL | libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE;
+
+/// Copy the runh binary into a sealed memfd.
+pub fn create_sealed_copy() -> OwnedFd {
+	let name = std::ffi::CString::new(MEMFD_NAME).unwrap();
+	let fd =
+		unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING) };
+	if fd < 0 {
+		panic!(
+			"Could not create memfd for cloned runh binary: {}",
+			std::io::Error::last_os_error()
+		);
+	}
+	let mut memfd = unsafe { File::from_raw_fd(fd) };
+
+	let mut binary = File::open("/proc/self/exe").expect("Could not open runh binary!");
+	std::io::copy(&mut binary, &mut memfd).expect("Could not copy runh binary to memfd!");
+
+	if unsafe { libc::fcntl(memfd.as_raw_fd(), libc::F_ADD_SEALS, RUNH_MEMFD_SEALS) } < 0 {
+		panic!(
+			"Could not seal cloned runh binary: {}",
+			std::io::Error::last_os_error()
+		);
+	}
+
+	// Executing a file with open writable fds fails with ETXTBSY, so only a read-only fd is kept
+	let sealed_copy = OpenOptions::new()
+		.read(true)
+		.custom_flags(libc::O_CLOEXEC)
+		.open(format!("/proc/self/fd/{}", memfd.as_raw_fd()))
+		.expect("Could not reopen cloned runh binary read-only!");
+	drop(memfd);
+	debug!("Created sealed copy of the runh binary");
+	OwnedFd::from(sealed_copy)
+}
+
+/// Path to execute the sealed copy from a child of the current process. The path refers to
+/// the fd table of the parent, so the fd cannot be clobbered by fd mappings of the child.
+pub fn get_exec_path(memfd: &OwnedFd) -> PathBuf {
+	PathBuf::from(format!(
+		"/proc/{}/fd/{}",
+		std::process::id(),
+		memfd.as_raw_fd()
+	))
+}
+
+/// Replace the current process by a sealed copy of the runh binary with the same arguments.
+pub fn reexec_sealed_copy() -> ! {
+	let sealed_copy = create_sealed_copy();
+	let mut args = std::env::args_os();
+	let error = Command::new(get_exec_path(&sealed_copy))
+		.arg0(
+			args.next()
+				.expect("Could not determine name of the runh binary!"),
+		)
+		.args(args)
+		.exec();
+	panic!(
+		"Could not execute sealed copy of the runh binary: {}",
+		error
+	)
+}
+
+/// Checks whether the current process was executed from a sealed copy of the runh binary.
+pub fn is_cloned_binary() -> bool {
+	let Ok(binary) = File::open("/proc/self/exe") else {
+		return false;
+	};
+	let seals = unsafe { libc::fcntl(binary.as_raw_fd(), libc::F_GET_SEALS) };
+	seals >= 0 && seals & RUNH_MEMFD_SEALS == RUNH_MEMFD_SEALS
+}
@@ -0,0 +1,105 @@
+use nix::sys::socket::{ControlMessage, MsgFlags};
+use serde_json::Value;
+use std::error::Error;
+use std::io::{IoSlice, Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Time to wait for cloud-hypervisor to create its API socket
+const API_SOCKET_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A minimal client for the REST API of cloud-hypervisor.
+pub struct ApiClient {
+	stream: UnixStream,
+}
+
+impl ApiClient {
+	/// Connect to the API socket, which is created by cloud-hypervisor shortly after its start.
+	pub fn connect(socket_path: &Path) -> Result<Self, Box<dyn Error>> {
+		let deadline = Instant::now() + API_SOCKET_TIMEOUT;
+		let stream = loop {
+			match UnixStream::connect(socket_path) {
+				Ok(stream) => break stream,
+				Err(_) if Instant::now() < deadline => {
+					std::thread::sleep(Duration::from_millis(10))
+				}
+				Err(err) => return Err(err.into()),
+			}
+		};
+		stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+		Ok(Self { stream })
+	}
+
+	/// Send a PUT request to an endpoint below /api/v1. Files that are sent along with the
+	/// request (e.g. tap devices) are passed as ancillary data.
+	pub fn put(
+		&mut self,
+		endpoint: &str,
+		body: Option<&Value>,
+		fds: &[RawFd],
+	) -> Result<(), Box<dyn Error>> {
+		debug!("Sending cloud-hypervisor API request {}", endpoint);
+		let body = body.map(Value::to_string).unwrap_or_default();
+		let mut request = format!("PUT /api/v1/{endpoint} HTTP/1.1\r\nHost: localhost\r\n");
+		if !body.is_empty() {
+			request.push_str(&format!(
+				"Content-Type: application/json\r\nContent-Length: {}\r\n",
+				body.len()
+			));
+		}
+		request.push_str("\r\n");
+
+		if fds.is_empty() {
+			self.stream.write_all(request.as_bytes())?;
+		} else {
+			let cmsgs = [ControlMessage::ScmRights(fds)];
+			let sent = nix::sys::socket::sendmsg::<()>(
+				self.stream.as_raw_fd(),
+				&[IoSlice::new(request.as_bytes())],
+				&cmsgs,
+				MsgFlags::empty(),
+				None,
+			)?;
+			self.stream.write_all(&request.as_bytes()[sent..])?;
+		}
+		self.stream.write_all(body.as_bytes())?;
+
+		let (status, response) = self.read_response()?;
+		if (200..300).contains(&status) {
+			Ok(())
+		} else {
+			Err(format!("API request {endpoint} failed with status {status}: {response}").into())
+		}
+	}
+
+	/// Read the status code and body of the next response.
+	fn read_response(&mut self) -> Result<(u16, String), Box<dyn Error>> {
+		let mut header = Vec::new();
+		let mut byte = [0u8; 1];
+		while !header.ends_with(b"\r\n\r\n") {
+			if self.stream.read(&mut byte)? == 0 {
+				return Err("API socket was closed".into());
+			}
+			header.push(byte[0]);
+		}
+		let header = String::from_utf8_lossy(&header);
+
+		let status = header
+			.split_whitespace()
+			.nth(1)
+			.and_then(|status| status.parse::<u16>().ok())
+			.ok_or_else(|| format!("Invalid API response {header:?}"))?;
+		let content_length = header
+			.lines()
+			.filter_map(|line| line.split_once(':'))
+			.find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+			.and_then(|(_, length)| length.trim().parse::<usize>().ok())
+			.unwrap_or(0);
+		let mut body = vec![0u8; content_length];
+		self.stream.read_exact(&mut body)?;
+
+		Ok((status, String::from_utf8_lossy(&body).into_owned()))
+	}
+}
@@ -1,4 +1,9 @@
-use std::{fs::File, io::IoSlice, os::unix::prelude::AsRawFd, path::PathBuf};
+use std::{
+	fs::File,
+	io::IoSlice,
+	os::unix::prelude::{AsRawFd, RawFd},
+	path::PathBuf,
+};
 
 use nix::{
 	fcntl::OFlag,
@@ -9,9 +14,15 @@ use crate::mounts;
 
 nix::ioctl_write_ptr_bad!(ioctl_set_winsize, libc::TIOCSWINSZ, libc::winsize);
 
+nix::ioctl_read_bad!(ioctl_get_winsize, libc::TIOCGWINSZ, libc::winsize);
+
 nix::ioctl_write_int_bad!(ioctl_set_ctty, libc::TIOCSCTTY);
 
-pub fn setup_console(console_socket: File, win_size: Option<&nix::pty::Winsize>) {
+pub fn setup_console(
+	console_socket: File,
+	win_size: Option<&nix::pty::Winsize>,
+	mount_console: bool,
+) {
 	// Open a new PTY master
 	let master_fd = nix::pty::posix_openpt(OFlag::O_RDWR | OFlag::O_CLOEXEC)
 		.expect("Could not open pty master!");
@@ -28,7 +39,9 @@ pub fn setup_console(console_socket: File, win_size: Option<&nix::pty::Winsize>)
 			.expect("Could not set winsize using ioctl!");
 	}
 
-	mounts::mount_console(&PathBuf::from(&slave_name));
+	if mount_console {
+		mounts::mount_console(&PathBuf::from(&slave_name));
+	}
 
 	//Send master fd over console_socket
 	nix::sys::socket::sendmsg::<()>(
@@ -55,3 +68,17 @@ pub fn setup_console(console_socket: File, win_size: Option<&nix::pty::Winsize>)
 
 	//master_fd auto-closes on drop
 }
+
+/// Copy the window size of the terminal `from` to the terminal `to`.
+/// Errors are ignored, e.g. if `from` is not a terminal.
+pub fn copy_winsize(from: RawFd, to: RawFd) {
+	let mut winsize = nix::pty::Winsize {
+		ws_row: 0,
+		ws_col: 0,
+		ws_xpixel: 0,
+		ws_ypixel: 0,
+	};
+	if unsafe { ioctl_get_winsize(from, &mut winsize) }.is_ok() {
+		let _ = unsafe { ioctl_set_winsize(to, &winsize) };
+	}
+}
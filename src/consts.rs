@@ -1,5 +1,35 @@
+pub const CREATE_ACK_CGROUPS: u8 = 0x01;
+pub const INIT_REQ_ID_MAPPINGS: u8 = 0x02;
+pub const CREATE_ACK_ID_MAPPINGS: u8 = 0x03;
 pub const INIT_REQ_PRESTART_HOOKS: u8 = 0x10;
 pub const CREATE_ACK_PRESTART_HOOKS: u8 = 0x11;
 pub const INIT_READY_TO_EXECV: u8 = 0xAA;
 
 pub const OCI_STATE_VERSION: &str = "1.0.2";
+
+/// Directory of the VMM sockets inside hermit containers. It is bind-mounted from the
+/// directory VMM_SOCKET_STATE_DIR in the state directory of the container.
+pub const VMM_SOCKET_DIR: &str = "/run/runh";
+pub const VMM_SOCKET_STATE_DIR: &str = "vmm";
+/// Name of the QMP socket of QEMU in VMM_SOCKET_DIR
+pub const QMP_SOCKET_NAME: &str = "qmp.sock";
+/// Name of the API socket of cloud-hypervisor in VMM_SOCKET_DIR
+pub const CLOUD_HYPERVISOR_API_SOCKET_NAME: &str = "ch-api.sock";
+/// Path of the vhost-user socket of virtiofsd inside hermit containers
+pub const VIRTIOFSD_SOCKET_PATH: &str = "/run/vhostqemu";
+
+/// Annotation to configure how long a hermit VM may take to power down on SIGTERM (in seconds)
+pub const ANNOTATION_SHUTDOWN_GRACE_PERIOD: &str = "org.hermit.runh.shutdown-grace-period";
+pub const DEFAULT_SHUTDOWN_GRACE_PERIOD: u64 = 10;
+
+/// Annotations to configure the VM of a hermit container, see the README for their values
+pub const ANNOTATION_VM_VMM: &str = "org.hermit.runh.vm.vmm";
+pub const ANNOTATION_VM_MICROVM: &str = "org.hermit.runh.vm.microvm";
+pub const ANNOTATION_VM_MEMORY: &str = "org.hermit.runh.vm.memory";
+pub const ANNOTATION_VM_SMP: &str = "org.hermit.runh.vm.smp";
+pub const ANNOTATION_VM_KVM: &str = "org.hermit.runh.vm.kvm";
+pub const ANNOTATION_VM_NETMODE: &str = "org.hermit.runh.vm.netmode";
+pub const ANNOTATION_VM_USER_PORT: &str = "org.hermit.runh.vm.user-port";
+pub const ANNOTATION_VM_EXTRA_KERNEL_ARGS: &str = "org.hermit.runh.vm.extra-kernel-args";
+/// Annotation to configure the memory (in MiB) that is reserved for the VMM, if a memory limit is set
+pub const ANNOTATION_VMM_MEMORY_OVERHEAD: &str = "org.hermit.runh.vm.memory-overhead";
@@ -1,4 +1,7 @@
 use std::cmp::Ordering;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
 
 use getset::Getters;
 use oci_spec::runtime::Spec;
@@ -35,6 +38,12 @@ impl OCIContainer {
 			spec: Spec::load(config.to_str().unwrap()).expect("Unable to load config file"),
 		}
 	}
+
+	/// Load the container that `runh create` stored in the given container directory.
+	pub fn load(container_dir: &Path) -> Option<Self> {
+		let file = File::open(container_dir.join("container.json")).ok()?;
+		serde_json::from_reader(BufReader::new(file)).ok()
+	}
 }
 
 impl Eq for OCIContainer {}
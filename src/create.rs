@@ -1,15 +1,21 @@
+use crate::cgroups;
+use crate::cloned_binary;
 use crate::hermit;
+use crate::hooks;
+use crate::image::{self, InspectError};
 use crate::logging::LogLevel;
 use crate::mounts;
+use crate::namespaces;
 use crate::rootfs;
+use crate::rootless;
+use crate::seccomp;
 use crate::state;
+use crate::systemd;
 use command_fds::{CommandFdExt, FdMapping};
 use nix::fcntl::OFlag;
 use nix::sys::socket;
 use nix::sys::socket::SockFlag;
 use nix::sys::stat::Mode;
-use nix::unistd::Gid;
-use nix::unistd::Uid;
 use std::borrow::Cow;
 use std::fs::File;
 use std::fs::OpenOptions;
@@ -17,11 +23,11 @@ use std::io::prelude::*;
 use std::os::unix::fs;
 use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::net::UnixStream;
-use std::os::unix::prelude::CommandExt;
 use std::path::PathBuf;
 use std::str::FromStr;
 
 use crate::container::OCIContainer;
+use crate::vm_config::VmConfig;
 
 #[allow(clippy::too_many_arguments)]
 pub fn create_container(
@@ -31,8 +37,11 @@ pub fn create_container(
 	pidfile: Option<PathBuf>,
 	console_socket: Option<PathBuf>,
 	hermit_env: Option<PathBuf>,
+	hermit_env_source: Option<PathBuf>,
 	debug_config: bool,
 	child_log_level: LogLevel,
+	inherit_stdin: bool,
+	systemd_cgroup: bool,
 ) {
 	let _ = std::fs::create_dir(&project_dir);
 
@@ -86,46 +95,77 @@ pub fn create_container(
 	.expect("Could not parse path to rootfs!");
 
 	//Check for args[0] and detect hermit container
-	let exec_args = &container
-		.spec()
-		.process()
-		.as_ref()
-		.unwrap()
-		.args()
-		.as_ref()
-		.unwrap();
-
-	let exec_path_rel = PathBuf::from(
-		exec_args
-			.first()
-			.expect("Container spec does not contain any args!"),
-	);
-	let exec_path_abs = rootfs::resolve_in_rootfs(&exec_path_rel, &bundle_rootfs_path_abs);
-	let is_hermit_container = if exec_path_abs.exists() {
-		hermit::is_hermit_app(&exec_path_abs)
-	} else {
-		warn!("Could not find args-executable at current point in lifecycle. We will check again later, but hermit executables will NOT be detected!");
-		false
+	let is_hermit_container = match image::inspect_executable(
+		container.spec(),
+		&bundle_rootfs_path_abs,
+	) {
+		Ok(info) => {
+			let is_hermit_app = info.is_hermit_app();
+			if is_hermit_app {
+				debug!(
+					"Found hermit application at {:?} with entry version {}",
+					info.path,
+					info.hermit_note
+						.as_ref()
+						.map_or("unknown", |note| note.entry_version.as_str())
+				);
+			}
+			is_hermit_app
+		}
+		Err(err @ InspectError::NotFound(_)) => {
+			warn!("{}. It might be created at a later point in the lifecycle, but hermit executables will NOT be detected!", err);
+			false
+		}
+		Err(err @ InspectError::Unreadable(..)) => {
+			warn!("{}. Assuming non-hermit container...", err);
+			false
+		}
+		Err(InspectError::NotElf(..)) => {
+			warn!("Could not parse content of args-executable in ELF format. Might be a script file. Assuming non-hermit container...");
+			false
+		}
 	};
 	if is_hermit_container {
 		info!("Detected RustyHermit executable. Creating container in hermit mode!");
 		//Setup hermit environment
-		hermit::prepare_environment(&project_dir, &hermit_env);
+		let result = VmConfig::from_spec(container.spec())
+			.map_err(|err| format!("Invalid VM configuration: {}", err))
+			.and_then(|_| {
+				hermit::prepare_environment(&project_dir, &hermit_env, &hermit_env_source)
+					.map_err(|err| err.to_string())
+			});
+		if let Err(err) = result {
+			// The container can be created again with the same id, after the error is fixed
+			let _ = std::fs::remove_dir_all(&container_dir);
+			panic!("{}", err);
+		}
+	}
+
+	let rootless = rootless::is_rootless();
+	if rootless && !namespaces::has_new_user_namespace(container.spec()) {
+		panic!("Rootless containers require a new user namespace with uid and gid mappings! Try runh spec --rootless.");
 	}
 
 	//Setup exec fifo
 	let fifo_location = container_dir.join("exec.fifo");
-	let old_mask = Mode::from_bits_truncate(0o000);
-	nix::unistd::mkfifo(&fifo_location, Mode::from_bits_truncate(0o644))
+	// The fifo has to be writable for the container process after switching the user
+	let old_mask = nix::sys::stat::umask(Mode::empty());
+	nix::unistd::mkfifo(&fifo_location, Mode::from_bits_truncate(0o622))
 		.expect("Could not create fifo!");
 
 	let _ = nix::sys::stat::umask(old_mask);
-	nix::unistd::chown(
-		&fifo_location,
-		Some(Uid::from_raw(0)),
-		Some(Gid::from_raw(0)),
-	)
-	.expect("could not call chown!");
+	// The container root user has to be able to open the fifo for writing
+	let (root_uid, root_gid) = namespaces::get_host_root_ids(container.spec());
+	nix::unistd::chown(&fifo_location, Some(root_uid), Some(root_gid))
+		.expect("could not call chown!");
+
+	// The VMM sockets of hermit containers are created by the container root user
+	let vmm_socket_dir = container_dir.join(crate::consts::VMM_SOCKET_STATE_DIR);
+	if is_hermit_container {
+		std::fs::create_dir(&vmm_socket_dir).expect("Could not create VMM socket directory!");
+		nix::unistd::chown(&vmm_socket_dir, Some(root_uid), Some(root_gid))
+			.expect("could not call chown!");
+	}
 
 	let fifo = OpenOptions::new()
 		.custom_flags(libc::O_PATH | libc::O_CLOEXEC)
@@ -170,6 +210,7 @@ pub fn create_container(
 	});
 
 	//Setup file system
+	let mut rootfs_overlay = None;
 	let rootfs_path_abs = if is_hermit_container {
 		let overlay_root = container_dir.join("rootfs");
 		let overlay_workdir = overlay_root.join("work");
@@ -188,14 +229,21 @@ pub fn create_container(
 			overlay_upperdir.as_os_str().to_str().unwrap(),
 			overlay_workdir.as_os_str().to_str().unwrap()
 		);
-		nix::mount::mount::<str, PathBuf, str, str>(
-			Some("overlay"),
-			&overlay_mergeddir,
-			Some("overlay"),
-			nix::mount::MsFlags::empty(),
-			Some(datastr.as_str()),
-		)
-		.unwrap_or_else(|err| panic!("Could not create overlay-fs at {:?}: {}", overlay_root, err));
+		if rootless {
+			// Unprivileged users can only mount the overlay inside the user namespace of the container
+			rootfs_overlay = Some(datastr);
+		} else {
+			nix::mount::mount::<str, PathBuf, str, str>(
+				Some("overlay"),
+				&overlay_mergeddir,
+				Some("overlay"),
+				nix::mount::MsFlags::empty(),
+				Some(datastr.as_str()),
+			)
+			.unwrap_or_else(|err| {
+				panic!("Could not create overlay-fs at {:?}: {}", overlay_root, err)
+			});
+		}
 		Cow::from(overlay_mergeddir.canonicalize().unwrap())
 	} else {
 		Cow::from(&bundle_rootfs_path_abs)
@@ -246,13 +294,38 @@ pub fn create_container(
 		});
 	};
 
-	let _ = std::process::Command::new("/proc/self/exe")
+	// runh init loads the seccomp filter right before exec, when runh create is already gone,
+	// so the connection to the seccomp agent is established here.
+	let seccomp_listener = container
+		.spec()
+		.linux()
+		.as_ref()
+		.and_then(|linux| linux.seccomp().as_ref())
+		.filter(|seccomp| seccomp::uses_notify(seccomp))
+		.map(seccomp::connect_listener);
+	let seccomp_listener_fd = if let Some(stream) = seccomp_listener {
+		child_fd_mappings.push(FdMapping {
+			parent_fd: stream.into(),
+			child_fd: 8,
+		});
+		"8"
+	} else {
+		""
+	};
+
+	// Prevent CVE-2019-5736 by running runh init from a sealed copy of the binary
+	let cloned_binary = cloned_binary::create_sealed_copy();
+	let mut init_process = std::process::Command::new(cloned_binary::get_exec_path(&cloned_binary))
 		.arg("-l")
 		.arg(child_log_level.as_str())
 		.arg("--log-format")
 		.arg("json")
 		.arg("init")
-		.stdin(std::process::Stdio::null())
+		.stdin(if inherit_stdin {
+			std::process::Stdio::inherit()
+		} else {
+			std::process::Stdio::null()
+		})
 		.fd_mappings(child_fd_mappings)
 		.expect("Unable to pass fifo fd to child!")
 		.env("RUNH_FIFOFD", "3")
@@ -261,8 +334,13 @@ pub fn create_container(
 		.env("RUNH_LOG_PIPE", "6")
 		.env("RUNH_CONSOLE", "7")
 		.env("RUNH_HERMIT_CONTAINER", is_hermit_container.to_string())
+		.env("RUNH_ROOTLESS", rootless.to_string())
+		.env("RUNH_ROOTFS_OVERLAY", rootfs_overlay.unwrap_or_default())
+		.env("RUNH_VMM_SOCKET_DIR", vmm_socket_dir)
+		.env("RUNH_SECCOMP_LISTENER", seccomp_listener_fd)
 		.spawn()
 		.expect("Unable to spawn runh init process");
+	drop(cloned_binary);
 
 	debug!("Waiting for first message from child...");
 	let mut init_pipe = File::from(parent_socket_fd);
@@ -314,6 +392,27 @@ pub fn create_container(
 			.expect("Could not write hermit env path to init pipe!");
 	}
 
+	if namespaces::has_new_user_namespace(container.spec()) {
+		debug!("Waiting for runh init to request id mappings");
+		init_pipe
+			.read_exact(&mut buffer)
+			.expect("Could not read from init pipe!");
+		if buffer[0] != crate::consts::INIT_REQ_ID_MAPPINGS {
+			panic!(
+				"Received invalid signal from runh init! Expected {:x}, got {:x}",
+				crate::consts::INIT_REQ_ID_MAPPINGS,
+				buffer[0]
+			);
+		}
+		namespaces::write_id_mappings(
+			init_process.id() as i32,
+			container.spec().linux().as_ref().unwrap(),
+		);
+		init_pipe
+			.write_all(&[crate::consts::CREATE_ACK_ID_MAPPINGS])
+			.expect("Unable to write to init-pipe!");
+	}
+
 	debug!("Waiting for runh init to send grandchild PID");
 	let mut pid_buffer = [0; 4];
 	init_pipe
@@ -321,10 +420,46 @@ pub fn create_container(
 		.expect("Could not read from init pipe!");
 
 	let pid = i32::from_le_bytes(pid_buffer);
+	// The first init stage exits right after reporting the PID of the container process
+	init_process
+		.wait()
+		.expect("Could not wait for runh init parent process!");
 	if let Some(pid_file_path) = pidfile {
 		let mut file = std::fs::File::create(pid_file_path).expect("Could not create pid-File!");
 		write!(file, "{pid}").expect("Could not write to pid-file!");
 	}
+
+	//Setup cgroup before the container process continues
+	let cgroup_path = cgroups::get_cgroup_path(container.spec(), id);
+	if !cgroups::is_cgroup_v2() {
+		warn!("Host does not use the unified cgroup v2 hierarchy. Resource limits will NOT be applied!");
+	} else if rootless && !systemd_cgroup && !cgroups::can_create_cgroup(&cgroup_path) {
+		warn!(
+			"Cgroup {:?} is not writable in rootless mode. Resource limits will NOT be applied!",
+			cgroup_path
+		);
+	} else {
+		let resources = container
+			.spec()
+			.linux()
+			.as_ref()
+			.and_then(|linux| linux.resources().as_ref());
+		let cgroup_path = if systemd_cgroup {
+			let cgroup_path = systemd::start_scope(container.spec(), id, pid);
+			if let Some(resources) = resources {
+				cgroups::apply_resources(&cgroup_path, resources);
+			}
+			cgroup_path
+		} else {
+			cgroups::create_cgroup(&cgroup_path, resources, pid);
+			cgroup_path
+		};
+		cgroups::save_cgroup_path(&container_dir, &cgroup_path);
+	}
+	init_pipe
+		.write_all(&[crate::consts::CREATE_ACK_CGROUPS])
+		.expect("Unable to write to init-pipe!");
+
 	let mut sig_buffer = [0u8];
 
 	//Running pre-start hooks
@@ -351,68 +486,35 @@ pub fn create_container(
 		.expect("Could not create state-file in container dir!");
 	write!(state_file, "{pid}").expect("Could not write pid to state-file!");
 
-	debug!("Running prestart hooks...");
-	if let Some(hooks) = container.spec().hooks().as_ref() {
-		let state = state::State {
-			version: String::from(crate::consts::OCI_STATE_VERSION),
-			id: container.id().clone(),
-			status: String::from("created"),
-			pid: Some(pid),
-			bundle: container.bundle().clone(),
-			annotations: container.spec().annotations().clone(),
-		};
+	let state = state::State {
+		version: String::from(crate::consts::OCI_STATE_VERSION),
+		id: container.id().clone(),
+		status: String::from("creating"),
+		pid: Some(pid),
+		bundle: container.bundle().clone(),
+		annotations: container.spec().annotations().clone(),
+	};
 
-		if let Some(prestart_hooks) = hooks.prestart().as_ref() {
-			for hook in prestart_hooks {
-				let mut cmd = std::process::Command::new(hook.path());
-				if let Some(args) = &hook.args() {
-					if !args.is_empty() {
-						cmd.arg0(&args[0]);
-					}
-					if args.len() > 1 {
-						cmd.args(&args[1..]);
-					}
-				}
-				if let Some(env) = &hook.env() {
-					for var in env {
-						let (name, value) = var.split_once('=').unwrap_or_else(|| {
-							panic!("Could not parse environment variable: {}", var)
-						});
-						cmd.env(name, value);
-					}
-				}
-				if hook.timeout().is_some() {
-					warn!("The timeout set for prestart hook {:?} is currently unimplemented and will be ignored!", hook.path());
-				}
-				cmd.stderr(std::process::Stdio::piped());
-				cmd.stdin(std::process::Stdio::piped());
-				let mut child = cmd.spawn().unwrap_or_else(|_| {
-					panic!("Unable to spawn prestart hook process {:?}", hook.path())
-				});
-				write!(
-					child.stdin.take().unwrap(),
-					"{}",
-					serde_json::to_string(&state).unwrap()
-				)
-				.expect("Could not write container state to hook process stdin!");
-
-				let ret = child.wait_with_output().unwrap();
-				if !ret.status.success() {
-					panic!(
-						"prestart hook {:?} returned exit status {}. Stderr: {}",
-						hook.path(),
-						ret.status,
-						String::from_utf8(ret.stderr).unwrap()
-					);
-				}
-			}
-		}
+	if let Some(hooks) = container.spec().hooks().as_ref() {
+		debug!("Running prestart and createRuntime hooks...");
+		hooks::run_hooks(hooks.prestart(), &state, "prestart")
+			.and_then(|_| hooks::run_hooks(hooks.create_runtime(), &state, "createRuntime"))
+			.unwrap_or_else(|err| panic!("{}", err));
 	}
 
 	init_pipe
 		.write_all(&[crate::consts::CREATE_ACK_PRESTART_HOOKS])
 		.expect("Unable to write to init-pipe!");
 
+	//send container state for the createContainer and startContainer hooks to child
+	let state_json = serde_json::to_string(&state).unwrap();
+	init_pipe
+		.write_all(&state_json.len().to_le_bytes())
+		.expect("Could not write container state size to init pipe!");
+	init_pipe
+		.write_all(state_json.as_bytes())
+		.expect("Could not write container state to init pipe!");
+
 	//Waiting for init
 	debug!("Waiting for runh init to get ready to execv!");
 
@@ -1,10 +1,18 @@
+use nix::errno::Errno;
 use nix::mount::MntFlags;
+use nix::sys::signal::Signal;
 // use nix::sched::CloneFlags;
 
+use crate::cgroups;
+use crate::container::OCIContainer;
+use crate::hooks;
 use crate::kill;
+use crate::rootless;
 // use crate::network;
 use crate::state;
+use crate::systemd;
 use std::fs;
+use std::os::unix::fs::PermissionsExt;
 // use std::fs::File;
 // use std::io::BufReader;
 // use std::os::unix::prelude::AsRawFd;
@@ -33,7 +41,7 @@ pub fn delete_container(project_dir: PathBuf, id: &str, force: bool) {
 				panic!("Tried to delete a container that is not stopped!");
 			} else if container_state.status != "creating" {
 				warn!("Container is still running. Force-deleting...");
-				kill::kill_container(project_dir.clone(), id, "SIGKILL", false);
+				kill::kill_container(project_dir.clone(), id, "SIGKILL", true);
 			} else {
 				warn!("Container has not finished creation. Force-deleting...");
 			}
@@ -42,10 +50,15 @@ pub fn delete_container(project_dir: PathBuf, id: &str, force: bool) {
 		let container_dir = project_dir.join(id);
 
 		let rootfs_overlay_dir = container_dir.join("rootfs/merged");
-		if rootfs_overlay_dir.exists() {
-			nix::mount::umount2(&rootfs_overlay_dir, MntFlags::MNT_DETACH).unwrap_or_else(|_| {
-				panic!("Could not unmount overlay at {:?}", rootfs_overlay_dir)
-			});
+		// In rootless mode, the overlay is only mounted in the mount namespace of the container
+		if rootfs_overlay_dir.exists() && !rootless::is_rootless() {
+			match nix::mount::umount2(&rootfs_overlay_dir, MntFlags::MNT_DETACH) {
+				Ok(_) | Err(Errno::EINVAL) => {}
+				Err(err) => panic!(
+					"Could not unmount overlay at {:?}: {}",
+					rootfs_overlay_dir, err
+				),
+			}
 		}
 
 		// match reset_network_namespace(&container_dir) {
@@ -53,11 +66,36 @@ pub fn delete_container(project_dir: PathBuf, id: &str, force: bool) {
 		// 	Err(err) => warn!("Failed to reset network namespace! Error: {}", err),
 		// }
 
+		if let Some(cgroup_path) = cgroups::load_cgroup_path(&container_dir) {
+			// Helper processes like virtiofsd might still be alive, even if the init process is not
+			if cgroup_path.exists() {
+				kill::signal_all_processes(&container_dir, None, Signal::SIGKILL);
+			}
+			if systemd::is_scope(&cgroup_path) {
+				systemd::stop_scope(&cgroup_path);
+			}
+			cgroups::remove_cgroup(&cgroup_path);
+		}
+
+		if let Some(hooks) = OCIContainer::load(&container_dir)
+			.and_then(|container| container.spec().hooks().clone())
+		{
+			let mut container_state = container_state;
+			container_state.status = String::from("stopped");
+			if let Err(err) = hooks::run_hooks(hooks.poststop(), &container_state, "poststop") {
+				warn!("{}", err);
+			}
+		}
+
+		// The kernel leaves the work directory of the overlay without permissions, which
+		// prevents unprivileged users from removing it
+		let overlay_workdir = container_dir.join("rootfs/work/work");
+		if rootless::is_rootless() && overlay_workdir.exists() {
+			let _ = fs::set_permissions(&overlay_workdir, fs::Permissions::from_mode(0o700));
+		}
+
 		// delete all temporary files
 		fs::remove_dir_all(container_dir).expect("Unable to delete container");
-
-	//Additionally to deleting all the files, we should also delete all remaining processes spawned by the container init process.
-	//However, without cgroup support there is currently no real way to do this as we do not know when (and how) the init process will be killed
 	} else {
 		warn!("Container `{id}` doesn't exists! Skipping deletion...");
 	}
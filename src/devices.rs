@@ -11,7 +11,13 @@ use oci_spec::runtime;
 
 use crate::{mounts, rootfs};
 
-pub fn create_devices(spec_devices: &Option<Vec<runtime::LinuxDevice>>, rootfs: &Path) {
+/// Create the default and configured devices in the rootfs. Inside user namespaces, mknod
+/// is not permitted, so the devices of the host are bind-mounted instead.
+pub fn create_devices(
+	spec_devices: &Option<Vec<runtime::LinuxDevice>>,
+	rootfs: &Path,
+	bind_devices: bool,
+) {
 	let mut default_devices = vec![
 		runtime::LinuxDeviceBuilder::default()
 			.path(PathBuf::from("/dev/null"))
@@ -105,7 +111,16 @@ pub fn create_devices(spec_devices: &Option<Vec<runtime::LinuxDevice>>, rootfs:
 			}),
 		));
 
-		//Just assume we are not in a user namespace and that mknod will work. Error out if it does not (FIXME ?)
+		if bind_devices {
+			mount_device(
+				rootfs,
+				dev.path(),
+				dev.major().try_into().unwrap(),
+				dev.minor().try_into().unwrap(),
+			);
+			continue;
+		}
+
 		let node_kind = match dev.typ() {
 			runtime::LinuxDeviceType::C => SFlag::S_IFCHR,
 			runtime::LinuxDeviceType::B => SFlag::S_IFBLK,
@@ -157,7 +172,7 @@ fn verify_device(path: &Path, major_exp: u64, minor_exp: u64) {
 	}
 }
 
-pub fn create_tun(rootfs: &Path, uid: Uid, gid: Gid) {
+pub fn create_tun(rootfs: &Path, uid: Uid, gid: Gid, bind_device: bool) {
 	let destination_relative = PathBuf::from("/dev/net/tun");
 	let destination_resolved = rootfs::resolve_in_rootfs(&destination_relative, rootfs);
 	if !destination_resolved.starts_with(rootfs) {
@@ -182,7 +197,11 @@ pub fn create_tun(rootfs: &Path, uid: Uid, gid: Gid) {
 		return;
 	}
 
-	//Just assume we are not in a user namespace and that mknod will work. Error out if it does not (FIXME ?)
+	if bind_device {
+		mount_device(rootfs, &destination_relative, 10, 200);
+		return;
+	}
+
 	let node_kind = SFlag::S_IFCHR;
 	let mode = Mode::from_bits(0o755u32).unwrap();
 	let device = nix::sys::stat::makedev(10, 200);
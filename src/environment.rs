@@ -0,0 +1,439 @@
+use crate::hermit::Arch;
+use crate::rootfs;
+use flate2::read::GzDecoder;
+use oci_spec::image::{self, Descriptor, ImageIndex, ImageManifest, MediaType};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs::{DirBuilder, File};
+use std::io::{BufReader, Read, Seek};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+
+/// Describes the installed hermit-environment. Located in the root of the environment.
+pub const MANIFEST_FILE: &str = ".runh-hermit-env.json";
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const WHITEOUT_PREFIX: &str = ".wh.";
+const WHITEOUT_OPAQUE: &str = ".wh..wh..opq";
+const ANNOTATION_IMAGE_VERSION: &str = "org.opencontainers.image.version";
+
+/// Origin of an installed hermit-environment
+struct EnvironmentManifest {
+	digest: String,
+	version: Option<String>,
+	layers: Vec<String>,
+}
+
+fn sha256_file(path: &Path) -> Result<String, Box<dyn Error>> {
+	let mut hasher = Sha256::new();
+	std::io::copy(&mut File::open(path)?, &mut hasher)?;
+	Ok(format!("sha256:{:x}", hasher.finalize()))
+}
+
+/// Open a tarball, which is optionally compressed with gzip.
+fn open_tarball(path: &Path) -> Result<tar::Archive<Box<dyn Read>>, Box<dyn Error>> {
+	let mut file = File::open(path)?;
+	let mut magic = [0u8; 2];
+	let is_gzip = file.read_exact(&mut magic).is_ok() && magic == GZIP_MAGIC;
+	file.rewind()?;
+
+	let reader: Box<dyn Read> = if is_gzip {
+		Box::new(GzDecoder::new(BufReader::new(file)))
+	} else {
+		Box::new(BufReader::new(file))
+	};
+	let mut archive = tar::Archive::new(reader);
+	archive.set_preserve_permissions(true);
+	archive.set_preserve_ownerships(nix::unistd::geteuid().is_root());
+	archive.set_overwrite(true);
+	archive.set_unpack_xattrs(false);
+	Ok(archive)
+}
+
+/// Unpack a (layer) tarball into the target directory. Whiteout files of image layers
+/// remove the files of previous layers. They are applied before the entries are unpacked,
+/// so that they do not hide files of their own layer.
+fn unpack_tarball(path: &Path, target: &Path) -> Result<(), Box<dyn Error>> {
+	debug!("Unpacking {:?} to {:?}", path, target);
+	let mut archive = open_tarball(path)?;
+	for entry in archive.entries()? {
+		let entry_path = entry?.path()?.into_owned();
+		let file_name = entry_path
+			.file_name()
+			.map(|name| name.to_string_lossy().into_owned())
+			.unwrap_or_default();
+		let Some(name) = file_name.strip_prefix(WHITEOUT_PREFIX) else {
+			continue;
+		};
+
+		// Symlinks of previous layers are resolved within the target, so that whiteouts
+		// cannot remove files outside of it
+		let dir = rootfs::resolve_in_rootfs(entry_path.parent().unwrap_or(Path::new("")), target);
+		if file_name == WHITEOUT_OPAQUE {
+			if let Ok(children) = std::fs::read_dir(dir) {
+				for child in children.flatten() {
+					remove_path(&child.path())?;
+				}
+			}
+		} else if matches!(name, "" | "." | "..") {
+			warn!("Ignoring invalid whiteout {:?} in {:?}", entry_path, path);
+		} else {
+			remove_path(&dir.join(name))?;
+		}
+	}
+
+	let mut archive = open_tarball(path)?;
+	for entry in archive.entries()? {
+		let mut entry = entry?;
+		let is_whiteout = entry
+			.path()?
+			.file_name()
+			.is_some_and(|name| name.to_string_lossy().starts_with(WHITEOUT_PREFIX));
+		if !is_whiteout {
+			entry.unpack_in(target)?;
+		}
+	}
+	Ok(())
+}
+
+fn remove_path(path: &Path) -> std::io::Result<()> {
+	match path.symlink_metadata() {
+		Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(path),
+		Ok(_) => std::fs::remove_file(path),
+		Err(_) => Ok(()),
+	}
+}
+
+fn get_blob_path(layout: &Path, descriptor: &Descriptor) -> PathBuf {
+	layout
+		.join("blobs")
+		.join(descriptor.digest().algorithm().as_ref())
+		.join(descriptor.digest().digest())
+}
+
+/// Locate a blob of an OCI image layout and verify its digest.
+fn get_verified_blob(layout: &Path, descriptor: &Descriptor) -> Result<PathBuf, Box<dyn Error>> {
+	let blob = get_blob_path(layout, descriptor);
+	let digest = descriptor.digest().to_string();
+	if !digest.starts_with("sha256:") {
+		return Err(format!("Unsupported digest algorithm of blob {}", digest).into());
+	}
+	if sha256_file(&blob)? != digest {
+		return Err(format!("Blob {:?} does not match its digest {}", blob, digest).into());
+	}
+	Ok(blob)
+}
+
+/// Select the manifest for the host from an image index. Nested indexes are resolved as well.
+fn select_manifest(
+	layout: &Path,
+	index: &ImageIndex,
+) -> Result<(Descriptor, ImageManifest), Box<dyn Error>> {
+	let host_arch = match Arch::host() {
+		Some(Arch::X86_64) => Some(image::Arch::Amd64),
+		Some(Arch::Aarch64) => Some(image::Arch::ARM64),
+		None => None,
+	};
+	let manifests = index.manifests();
+	let descriptor = manifests
+		.iter()
+		.find(|descriptor| {
+			descriptor
+				.platform()
+				.as_ref()
+				.is_some_and(|platform| Some(platform.architecture()) == host_arch.as_ref())
+		})
+		.or_else(|| {
+			manifests
+				.iter()
+				.find(|descriptor| descriptor.platform().is_none())
+		})
+		.ok_or("The image does not contain a manifest for the host architecture")?;
+
+	let blob = get_verified_blob(layout, descriptor)?;
+	if *descriptor.media_type() == MediaType::ImageIndex {
+		select_manifest(layout, &ImageIndex::from_file(blob)?)
+	} else {
+		Ok((descriptor.clone(), ImageManifest::from_file(blob)?))
+	}
+}
+
+/// Unpack the layers of an OCI image layout for the host architecture.
+fn install_oci_layout(layout: &Path, target: &Path) -> Result<EnvironmentManifest, Box<dyn Error>> {
+	let index = ImageIndex::from_file(layout.join("index.json"))?;
+	let (descriptor, manifest) = select_manifest(layout, &index)?;
+	info!(
+		"Installing hermit-environment from image {}",
+		descriptor.digest()
+	);
+
+	let mut layers = Vec::new();
+	for layer in manifest.layers() {
+		unpack_tarball(&get_verified_blob(layout, layer)?, target)?;
+		layers.push(layer.digest().to_string());
+	}
+
+	let version = [descriptor.annotations(), manifest.annotations()]
+		.iter()
+		.filter_map(|annotations| annotations.as_ref())
+		.find_map(|annotations| annotations.get(ANNOTATION_IMAGE_VERSION))
+		.cloned();
+	Ok(EnvironmentManifest {
+		digest: descriptor.digest().to_string(),
+		version,
+		layers,
+	})
+}
+
+/// Unpack a flat tarball of the environment, e.g. created by `docker export`.
+fn install_tarball(tarball: &Path, target: &Path) -> Result<EnvironmentManifest, Box<dyn Error>> {
+	let digest = sha256_file(tarball)?;
+	info!("Installing hermit-environment from tarball {}", digest);
+	unpack_tarball(tarball, target)?;
+	Ok(EnvironmentManifest {
+		digest,
+		version: None,
+		layers: Vec::new(),
+	})
+}
+
+fn install_to(source: &Path, staging: &Path) -> Result<(), Box<dyn Error>> {
+	let manifest = if source.join("oci-layout").exists() {
+		install_oci_layout(source, staging)?
+	} else if source.is_file() {
+		install_tarball(source, staging)?
+	} else {
+		return Err(format!("{:?} is neither an OCI image layout nor a tarball", source).into());
+	};
+
+	if !staging.join("hermit").exists() && !staging.join("bin").exists() {
+		warn!("The installed hermit-environment neither contains /bin nor /hermit!");
+	}
+
+	let manifest = json!({
+		"source": source.canonicalize()?,
+		"digest": manifest.digest,
+		"version": manifest.version,
+		"layers": manifest.layers,
+	});
+	std::fs::write(
+		staging.join(MANIFEST_FILE),
+		serde_json::to_string_pretty(&manifest)?,
+	)?;
+	Ok(())
+}
+
+/// Install a hermit-environment from an OCI image layout or a (gzipped) tarball. The environment
+/// is unpacked next to its final location and renamed afterwards, so that concurrent runh
+/// processes never observe a partially installed environment.
+pub fn install_environment(source: &Path, target: &Path) -> Result<(), Box<dyn Error>> {
+	let (Some(parent), Some(name)) = (target.parent(), target.file_name()) else {
+		return Err(format!("Invalid hermit-environment path {:?}", target).into());
+	};
+	let staging = parent.join(format!(
+		".{}.tmp-{}",
+		name.to_string_lossy(),
+		std::process::id()
+	));
+	DirBuilder::new()
+		.recursive(true)
+		.mode(0o755)
+		.create(&staging)
+		.map_err(|err| {
+			format!(
+				"Could not create staging directory {:?} for the hermit-environment: {}",
+				staging, err
+			)
+		})?;
+
+	if let Err(err) = install_to(source, &staging) {
+		let _ = std::fs::remove_dir_all(&staging);
+		return Err(format!(
+			"Could not install hermit-environment from {:?}: {}",
+			source, err
+		)
+		.into());
+	}
+
+	if let Err(err) = std::fs::rename(&staging, target) {
+		let _ = std::fs::remove_dir_all(&staging);
+		// Another runh process might have installed the environment in the meantime
+		if !target.is_dir() {
+			return Err(
+				format!("Could not move hermit-environment to {:?}: {}", target, err).into(),
+			);
+		}
+		info!(
+			"Hermit-environment at {:?} was installed concurrently",
+			target
+		);
+		return Ok(());
+	}
+	info!("Installed hermit-environment at {:?}", target);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use oci_spec::image::Digest as BlobDigest;
+	use std::str::FromStr;
+
+	fn test_dir(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("runh-env-{}-{}", std::process::id(), name));
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir).unwrap();
+		dir
+	}
+
+	/// Entries of a layer tarball. Entries without content are directories.
+	type Layer<'a> = &'a [(&'a str, Option<&'a [u8]>)];
+
+	fn write_layer(path: &Path, entries: Layer) {
+		let mut builder = tar::Builder::new(File::create(path).unwrap());
+		for (name, content) in entries {
+			let mut header = tar::Header::new_gnu();
+			header.set_uid(0);
+			header.set_gid(0);
+			header.set_mtime(0);
+			match content {
+				Some(content) => {
+					header.set_entry_type(tar::EntryType::Regular);
+					header.set_mode(0o644);
+					header.set_size(content.len() as u64);
+				}
+				None => {
+					header.set_entry_type(tar::EntryType::Directory);
+					header.set_mode(0o755);
+					header.set_size(0);
+				}
+			}
+			builder
+				.append_data(&mut header, name, content.unwrap_or_default())
+				.unwrap();
+		}
+		builder.finish().unwrap();
+	}
+
+	fn unpack_layers(name: &str, layers: &[Layer]) -> PathBuf {
+		let dir = test_dir(name);
+		let target = dir.join("target");
+		std::fs::create_dir(&target).unwrap();
+		for (i, entries) in layers.iter().enumerate() {
+			let layer = dir.join(format!("layer{i}.tar"));
+			write_layer(&layer, entries);
+			unpack_tarball(&layer, &target).unwrap();
+		}
+		target
+	}
+
+	#[test]
+	fn whiteouts_remove_files_of_previous_layers() {
+		let target = unpack_layers(
+			"whiteout",
+			&[
+				&[
+					("a", None),
+					("a/keep", Some(b"keep")),
+					("a/remove", Some(b"remove")),
+					("b", None),
+					("b/file", Some(b"file")),
+				],
+				&[("a/.wh.remove", Some(b"")), (".wh.b", Some(b""))],
+			],
+		);
+		assert!(target.join("a/keep").exists());
+		assert!(!target.join("a/remove").exists());
+		assert!(!target.join("a/.wh.remove").exists());
+		assert!(!target.join("b").exists());
+		std::fs::remove_dir_all(target.parent().unwrap()).unwrap();
+	}
+
+	#[test]
+	fn opaque_whiteouts_only_hide_previous_layers() {
+		let target = unpack_layers(
+			"opaque",
+			&[
+				&[("a", None), ("a/old", Some(b"old"))],
+				&[
+					("a", None),
+					("a/new", Some(b"new")),
+					("a/.wh..wh..opq", Some(b"")),
+				],
+			],
+		);
+		assert!(!target.join("a/old").exists());
+		assert_eq!(std::fs::read(target.join("a/new")).unwrap(), b"new");
+		assert!(!target.join("a/.wh..wh..opq").exists());
+		std::fs::remove_dir_all(target.parent().unwrap()).unwrap();
+	}
+
+	#[test]
+	fn invalid_whiteouts_are_ignored() {
+		let target = unpack_layers(
+			"invalid-whiteout",
+			&[
+				&[("a", None), ("a/file", Some(b"file"))],
+				&[
+					("a/.wh..", Some(b"")),
+					("a/.wh...", Some(b"")),
+					("a/.wh.", Some(b"")),
+				],
+			],
+		);
+		assert!(target.join("a/file").exists());
+		assert!(!target.join("a/.wh..").exists());
+		assert!(!target.join("a/.wh...").exists());
+		std::fs::remove_dir_all(target.parent().unwrap()).unwrap();
+	}
+
+	#[test]
+	fn blobs_are_verified_against_their_digest() {
+		let layout = test_dir("blobs");
+		let content = b"hermit";
+		let digest = format!("{:x}", Sha256::digest(content));
+		let blob_dir = layout.join("blobs/sha256");
+		std::fs::create_dir_all(&blob_dir).unwrap();
+		std::fs::write(blob_dir.join(&digest), content).unwrap();
+
+		let descriptor = Descriptor::new(
+			MediaType::ImageLayer,
+			content.len() as u64,
+			BlobDigest::from_str(&format!("sha256:{digest}")).unwrap(),
+		);
+		assert_eq!(
+			get_verified_blob(&layout, &descriptor).unwrap(),
+			blob_dir.join(&digest)
+		);
+
+		std::fs::write(blob_dir.join(&digest), b"tampered").unwrap();
+		assert!(get_verified_blob(&layout, &descriptor).is_err());
+		std::fs::remove_dir_all(&layout).unwrap();
+	}
+
+	#[test]
+	fn rejects_unsupported_digest_algorithms() {
+		let layout = test_dir("sha512");
+		let digest = "a".repeat(128);
+		std::fs::create_dir_all(layout.join("blobs/sha512")).unwrap();
+		std::fs::write(layout.join("blobs/sha512").join(&digest), b"hermit").unwrap();
+
+		let descriptor = Descriptor::new(
+			MediaType::ImageLayer,
+			6,
+			BlobDigest::from_str(&format!("sha512:{digest}")).unwrap(),
+		);
+		let err = get_verified_blob(&layout, &descriptor).unwrap_err();
+		assert!(err.to_string().contains("Unsupported digest algorithm"));
+		std::fs::remove_dir_all(&layout).unwrap();
+	}
+
+	#[test]
+	fn missing_environment_without_source_names_the_option() {
+		let project_dir = test_dir("missing");
+		let err = crate::hermit::prepare_environment(&project_dir, &None, &None).unwrap_err();
+		assert!(err.to_string().contains("--hermit-env-source"));
+		std::fs::remove_dir_all(&project_dir).unwrap();
+	}
+}
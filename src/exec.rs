@@ -0,0 +1,251 @@
+use crate::container::OCIContainer;
+use crate::state::State;
+use crate::{apparmor, capabilities, cgroups, cloned_binary, console, limits, namespaces};
+use crate::{paths, seccomp, state, user};
+use capctl::prctl;
+use nix::sys::wait::WaitStatus;
+use nix::unistd::ForkResult;
+use oci_spec::runtime;
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::os::fd::OwnedFd;
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+
+#[allow(clippy::too_many_arguments)]
+pub fn exec_container(
+	project_dir: PathBuf,
+	id: &str,
+	process_file: Option<PathBuf>,
+	command: Option<String>,
+	command_options: Vec<String>,
+	tty: bool,
+	cwd: Option<PathBuf>,
+	env: Vec<String>,
+	detach: bool,
+	pid_file: Option<PathBuf>,
+	console_socket: Option<PathBuf>,
+) {
+	// The exec process joins the namespaces of the container, so it has to run from a
+	// sealed copy of the binary like runh init (prevent CVE-2019-5736)
+	if !cloned_binary::is_cloned_binary() {
+		cloned_binary::reexec_sealed_copy();
+	}
+
+	let container_state = state::get_container_state(project_dir.clone(), id)
+		.unwrap_or_else(|| panic!("Could not query state for container {}", id));
+	if container_state.status != "created" && container_state.status != "running" {
+		panic!(
+			"Cannot execute a process in container {} with status {}!",
+			id, container_state.status
+		);
+	}
+	let pid = container_state.pid.unwrap();
+
+	let container = OCIContainer::load(&project_dir.join(id))
+		.unwrap_or_else(|| panic!("Could not load configuration of container {}", id));
+
+	let process: runtime::Process = if let Some(process_path) = process_file {
+		let process_file = File::open(&process_path)
+			.unwrap_or_else(|_| panic!("Could not open process file at {:?}", process_path));
+		serde_json::from_reader(BufReader::new(process_file)).unwrap_or_else(|err| {
+			panic!("Could not parse process file {:?}: {}", process_path, err)
+		})
+	} else {
+		let mut process = container
+			.spec()
+			.process()
+			.clone()
+			.expect("Container spec does not contain a process!");
+		let mut args = vec![command.expect("No command given for exec!")];
+		args.extend(command_options);
+		process.set_args(Some(args));
+		process.set_terminal(Some(tty));
+		if let Some(cwd) = cwd {
+			process.set_cwd(cwd);
+		}
+		if !env.is_empty() {
+			let mut process_env = process.env().clone().unwrap_or_default();
+			process_env.extend(env);
+			process.set_env(Some(process_env));
+		}
+		process
+	};
+
+	let terminal = process.terminal().unwrap_or(false);
+	if terminal && console_socket.is_none() {
+		panic!("Exec with a terminal requires a console socket!");
+	}
+
+	// Everything referring to host paths has to be opened before joining the mount namespace
+	let console_stream = if terminal {
+		let console_socket_path = console_socket.unwrap();
+		Some(
+			UnixStream::connect(&console_socket_path).unwrap_or_else(|_| {
+				panic!(
+					"Could not connect to socket named by console-socket path at {:?}",
+					console_socket_path
+				)
+			}),
+		)
+	} else {
+		None
+	};
+	let pid_file = pid_file
+		.map(|pid_file_path| File::create(pid_file_path).expect("Could not create pid-File!"));
+	let seccomp = container
+		.spec()
+		.linux()
+		.as_ref()
+		.and_then(|linux| linux.seccomp().clone());
+	let seccomp_listener = seccomp
+		.as_ref()
+		.filter(|seccomp| seccomp::uses_notify(seccomp))
+		.map(seccomp::connect_listener);
+
+	if let Some(cgroup_path) = cgroups::load_cgroup_path(&project_dir.join(id)) {
+		cgroups::join_cgroup(&cgroup_path, std::process::id() as i32);
+	}
+
+	// Deny the container access to /proc/<pid> of the exec process before entering it
+	prctl::set_dumpable(false).expect("Could not set process as non-dumpable!");
+	namespaces::join_process_namespaces(pid);
+
+	// Joining the PID namespace only affects newly created children
+	match unsafe { nix::unistd::fork() }.expect("Could not fork exec process!") {
+		ForkResult::Parent { child } => {
+			debug!("Spawned exec process with PID {}", child);
+			if let Some(mut file) = pid_file {
+				write!(file, "{child}").expect("Could not write to pid-file!");
+			}
+			if detach {
+				return;
+			}
+
+			let exit_code = match nix::sys::wait::waitpid(child, None)
+				.expect("Could not wait for exec process!")
+			{
+				WaitStatus::Exited(_, code) => code,
+				WaitStatus::Signaled(_, signal, _) => 128 + signal as i32,
+				status => panic!("Unexpected wait status {:?} of exec process!", status),
+			};
+			std::process::exit(exit_code);
+		}
+		ForkResult::Child => exec_process(
+			&process,
+			console_stream,
+			seccomp.as_ref(),
+			seccomp_listener,
+			&container_state,
+		),
+	}
+}
+
+fn exec_process(
+	process: &runtime::Process,
+	console_stream: Option<UnixStream>,
+	seccomp: Option<&runtime::LinuxSeccomp>,
+	seccomp_listener: Option<UnixStream>,
+	state: &State,
+) -> ! {
+	let _ = nix::unistd::setsid().expect("Could not set session ID");
+
+	if let Some(stream) = console_stream {
+		let win_size = process.console_size().as_ref().map(|b| nix::pty::Winsize {
+			ws_row: b.height() as u16,
+			ws_col: b.width() as u16,
+			ws_xpixel: 0,
+			ws_ypixel: 0,
+		});
+		console::setup_console(File::from(OwnedFd::from(stream)), win_size.as_ref(), false);
+	}
+
+	unsafe {
+		libc::clearenv();
+	}
+
+	if let Some(env) = process.env() {
+		for var in env {
+			let (name, value) = var
+				.split_once('=')
+				.unwrap_or_else(|| panic!("Could not parse environment variable: {}", var));
+			if !name.is_empty() {
+				std::env::set_var(name, value);
+			}
+		}
+	}
+
+	if !process.cwd().as_os_str().is_empty() {
+		nix::unistd::chdir(process.cwd()).unwrap_or_else(|err| {
+			panic!("Could not change directory to {:?}: {}", process.cwd(), err)
+		});
+	}
+
+	if let Some(rlimits) = process.rlimits() {
+		limits::set_rlimits(rlimits);
+	}
+	if let Some(oom_score_adj) = process.oom_score_adj() {
+		// The OOM score is already inherited from runh exec, so failures are not fatal
+		if let Err(err) = limits::set_oom_score_adj(oom_score_adj) {
+			warn!("Could not set oom_score_adj to {}: {}", oom_score_adj, err);
+		}
+	}
+	if let Some(profile) = process.apparmor_profile() {
+		apparmor::apply_profile(profile);
+	}
+
+	// Without no_new_privs, installing a seccomp filter requires CAP_SYS_ADMIN, which
+	// might be dropped by the user switch. Otherwise, the filter is loaded right before exec.
+	let no_new_privs = process.no_new_privileges().unwrap_or(false);
+	let mut seccomp_listener = seccomp_listener;
+	if let Some(seccomp) = seccomp.filter(|_| !no_new_privs) {
+		seccomp::apply_seccomp(seccomp, seccomp_listener.take(), state);
+	}
+
+	if let Some(capabilities) = process.capabilities() {
+		capabilities::apply_bounding_set(capabilities);
+		capabilities::set_keep_caps();
+	}
+	user::switch_user(process.user());
+	if let Some(capabilities) = process.capabilities() {
+		capabilities::clear_keep_caps();
+		capabilities::apply_capabilities(capabilities);
+	}
+
+	if no_new_privs {
+		debug!("set no_new_privileges");
+		prctl::set_no_new_privs().expect("Could not set no_new_privs flag!");
+	}
+
+	let exec_args = process
+		.args()
+		.as_ref()
+		.expect("Process does not contain any args!");
+	let exec_path_abs = paths::find_in_path(
+		PathBuf::from(
+			exec_args
+				.first()
+				.expect("Process does not contain any args!"),
+		),
+		None,
+	)
+	.expect("Could not determine location of args-executable!");
+
+	info!("Executing command {}", exec_args.join(" "));
+
+	let mut cmd = std::process::Command::new(exec_path_abs);
+	cmd.arg0(exec_args.first().unwrap());
+	if exec_args.len() > 1 {
+		cmd.args(exec_args.get(1..).unwrap());
+	}
+	cmd.envs(std::env::vars());
+
+	if let Some(seccomp) = seccomp.filter(|_| no_new_privs) {
+		seccomp::apply_seccomp(seccomp, seccomp_listener, state);
+	}
+	let error = cmd.exec();
+
+	//This point should not be reached on successful exec
+	panic!("exec failed with error {}", error)
+}
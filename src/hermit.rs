@@ -1,21 +1,68 @@
-use crate::network;
+use crate::cloud_hypervisor::ApiClient;
+use crate::qmp::QmpClient;
+use crate::vm_config::{VmConfig, VmmKind};
+use crate::{consts, environment, image, network};
 use goblin::elf;
-use goblin::elf64::header::EI_OSABI;
-use std::{fs, path::Path, path::PathBuf};
-
-pub fn is_hermit_app(path: &Path) -> bool {
-	let buffer = fs::read(path)
-		.unwrap_or_else(|_| panic!("Could not read content of args-executable at {:?}", path));
-	if let Ok(elf) = elf::Elf::parse(&buffer) {
-		elf.header.e_ident[EI_OSABI] == 0xFF
-	} else {
-		warn!("Could not parse content of args-executable in ELF format. Might be a script file. Assuming non-hermit container...");
-		false
+use oci_spec::runtime::Spec;
+use serde_json::{json, Value};
+use std::cell::Cell;
+use std::error::Error;
+use std::os::fd::RawFd;
+use std::{path::Path, path::PathBuf};
+
+/// Architecture of a hermit application and of the VM that runs it
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Arch {
+	#[default]
+	X86_64,
+	Aarch64,
+}
+
+impl Arch {
+	fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"x86_64" => Some(Arch::X86_64),
+			"aarch64" => Some(Arch::Aarch64),
+			_ => None,
+		}
+	}
+
+	/// Architecture of the host, if runh is able to run hermit VMs on it.
+	pub fn host() -> Option<Self> {
+		Self::from_name(std::env::consts::ARCH)
+	}
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Arch::X86_64 => "x86_64",
+			Arch::Aarch64 => "aarch64",
+		}
 	}
 }
 
-pub fn create_environment(_path: &Path) {
-	//TODO
+/// Determine the architecture of a hermit application from the e_machine field of its ELF header.
+pub fn get_app_arch(path: &Path) -> Arch {
+	let info = image::inspect_file(path)
+		.unwrap_or_else(|err| panic!("Could not inspect hermit application: {}", err));
+	info.arch.unwrap_or_else(|| {
+		panic!(
+			"Hermit application at {:?} has the unsupported architecture {}!",
+			path,
+			elf::header::machine_to_str(info.machine)
+		)
+	})
+}
+
+/// VMMs without an emulator can only run applications built for the host.
+fn ensure_native_app(vmm: &str, app_arch: Arch) {
+	if Arch::host() != Some(app_arch) {
+		panic!(
+			"{} cannot run {} hermit applications on a {} host!",
+			vmm,
+			app_arch.name(),
+			std::env::consts::ARCH
+		);
+	}
 }
 
 pub fn get_environment_path(project_dir: &Path, hermit_env_path: &Option<PathBuf>) -> PathBuf {
@@ -25,53 +72,488 @@ pub fn get_environment_path(project_dir: &Path, hermit_env_path: &Option<PathBuf
 	}
 }
 
-pub fn prepare_environment(project_dir: &Path, hermit_env_path: &Option<PathBuf>) {
+/// Make sure that the hermit-environment exists. A missing environment is installed
+/// from the given source.
+pub fn prepare_environment(
+	project_dir: &Path,
+	hermit_env_path: &Option<PathBuf>,
+	hermit_env_source: &Option<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
 	let environment_path = get_environment_path(project_dir, hermit_env_path);
-	if !environment_path.exists() {
-		create_environment(&environment_path);
-	} else if !environment_path.is_dir() {
-		panic!(
+	if environment_path.is_dir() {
+		return Ok(());
+	} else if environment_path.exists() {
+		return Err(format!(
 			"Environment path at {:?} exists but is not a directory!",
 			&environment_path
+		)
+		.into());
+	}
+
+	match hermit_env_source {
+		Some(source) => environment::install_environment(source, &environment_path),
+		None => Err(format!(
+			"Hermit-environment at {:?} does not exist! Install it with --hermit-env-source.",
+			environment_path
+		)
+		.into()),
+	}
+}
+
+/// Install the hermit-environment ahead of time, so that creating the first hermit
+/// container does not have to wait for it.
+pub fn install_environment(
+	project_dir: &Path,
+	hermit_env_path: &Option<PathBuf>,
+	hermit_env_source: &Option<PathBuf>,
+) {
+	if hermit_env_source.is_none() {
+		panic!("Installing the hermit-environment requires --hermit-env-source!");
+	}
+	let environment_path = get_environment_path(project_dir, hermit_env_path);
+	if environment_path.is_dir() {
+		info!(
+			"Hermit-environment at {:?} is already installed",
+			environment_path
 		);
+		return;
 	}
+	prepare_environment(project_dir, hermit_env_path, hermit_env_source)
+		.unwrap_or_else(|err| panic!("{}", err));
+}
+
+/// vCPUs and memory (in MiB) of a hermit VM
+#[derive(Debug)]
+pub struct VmResources {
+	pub cpus: u32,
+	pub memory: u64,
+	/// Guest memory (in MiB) that fits into the memory limit of the container
+	pub max_memory: Option<u64>,
+}
+
+const DEFAULT_VM_CPUS: u32 = 1;
+const DEFAULT_VM_MEMORY: u64 = 1024;
+const MIN_VM_MEMORY: u64 = 64;
+/// Memory reserved for the VMM process itself, if the container has a memory limit
+const DEFAULT_VMM_MEMORY_OVERHEAD: u64 = 128;
+
+/// Count the CPUs of a cpuset list like "0-3,6"
+fn count_cpuset_cpus(cpus: &str) -> Option<u32> {
+	cpus.split(',')
+		.filter(|range| !range.trim().is_empty())
+		.map(|range| match range.trim().split_once('-') {
+			Some((start, end)) => {
+				let start: u32 = start.parse().ok()?;
+				let end: u32 = end.parse().ok()?;
+				end.checked_sub(start).map(|count| count + 1)
+			}
+			None => range.trim().parse::<u32>().ok().map(|_| 1),
+		})
+		.sum()
+}
+
+/// Derive the size of the VM from the resource limits of the container, so that
+/// CPU and memory limits of the container manager also apply to the guest.
+/// The memory overhead (in MiB) is reserved for the VMM process, if a memory limit is set.
+pub fn get_vm_resources(spec: &Spec, memory_overhead: Option<u64>) -> Result<VmResources, String> {
+	let resources = spec
+		.linux()
+		.as_ref()
+		.and_then(|linux| linux.resources().as_ref());
+
+	let cpu = resources.and_then(|resources| resources.cpu().as_ref());
+	let quota_cpus = cpu.and_then(|cpu| {
+		let quota = cpu.quota().filter(|quota| *quota > 0)? as u64;
+		let period = cpu.period().filter(|period| *period > 0).unwrap_or(100000);
+		Some(quota.div_ceil(period) as u32)
+	});
+	let cpuset_cpus = cpu
+		.and_then(|cpu| cpu.cpus().as_ref())
+		.and_then(|cpus| {
+			let count = count_cpuset_cpus(cpus);
+			if count.is_none() {
+				warn!(
+					"Could not parse cpuset {}, ignoring it for the VM size",
+					cpus
+				);
+			}
+			count
+		})
+		.filter(|count| *count > 0);
+	let cpus = match (quota_cpus, cpuset_cpus) {
+		(Some(quota_cpus), Some(cpuset_cpus)) => quota_cpus.min(cpuset_cpus),
+		(Some(cpus), None) | (None, Some(cpus)) => cpus,
+		(None, None) => DEFAULT_VM_CPUS,
+	};
+
+	let memory_limit = resources
+		.and_then(|resources| resources.memory().as_ref())
+		.and_then(|memory| memory.limit())
+		.filter(|limit| *limit > 0)
+		.map(|limit| limit as u64 / (1024 * 1024));
+	let memory_overhead = memory_overhead.unwrap_or(DEFAULT_VMM_MEMORY_OVERHEAD);
+	let memory = match memory_limit {
+		Some(memory_limit) if memory_limit <= memory_overhead + MIN_VM_MEMORY => {
+			return Err(format!(
+				"Memory limit of {} MiB is too small for a VM, it has to exceed the VMM overhead of {} MiB by more than {} MiB",
+				memory_limit, memory_overhead, MIN_VM_MEMORY
+			));
+		}
+		Some(memory_limit) => memory_limit - memory_overhead,
+		None => DEFAULT_VM_MEMORY,
+	};
+
+	debug!("VM uses {} vCPUs and {} MiB memory", cpus, memory);
+	Ok(VmResources {
+		cpus,
+		memory,
+		max_memory: memory_limit.map(|_| memory),
+	})
 }
 
 pub enum NetworkConfig {
 	TapNetwork(network::VirtioNetworkConfig),
-	UserNetwork(u16),
+	UserNetwork(Option<u16>),
 	None,
 }
 
-pub fn get_qemu_args(
+/// File descriptors, which runh init opens for the VMM while it is still privileged
+#[derive(Debug, Default)]
+pub struct VmmFds {
+	pub tap: Option<RawFd>,
+	/// Listening socket of the QMP or API socket of the VMM
+	pub control: Option<RawFd>,
+}
+
+/// A hypervisor that runs hermit applications
+pub trait Vmm {
+	/// Command line of the VMM, starting with the name of its executable.
+	/// VMMs that are configured through an API prepare their configuration here.
+	fn get_args(
+		&mut self,
+		app: &str,
+		app_args: &[String],
+		netconf: &NetworkConfig,
+		vm_config: &VmConfig,
+		kvm: bool,
+		fds: &VmmFds,
+	) -> Vec<String>;
+
+	/// Name of the socket in VMM_SOCKET_DIR, through which runh controls the VMM. The socket
+	/// is created by runh init and passed to the VMM as listening socket.
+	fn control_socket(&self) -> Option<&'static str> {
+		None
+	}
+
+	/// Ask the guest to power down. Returns false if the VMM does not support this,
+	/// in which case the VMM is signalled instead.
+	fn power_down(&self) -> bool {
+		false
+	}
+
+	/// Whether the guest accesses the container file system through virtiofsd
+	fn uses_virtiofsd(&self, _vm_config: &VmConfig) -> bool {
+		false
+	}
+
+	/// Translate the exit status of the VMM into the exit code of the hermit application
+	fn get_exit_code(&self, status: i32) -> i32 {
+		status
+	}
+
+	/// Boot the VM after the VMM was spawned. VMMs that are configured on the command line
+	/// boot on their own.
+	fn boot(&self) -> Result<(), Box<dyn Error>> {
+		Ok(())
+	}
+}
+
+/// QEMU boots the application with the hermit-loader, which has to be located next to it.
+/// Applications for another architecture than the host are emulated.
+#[derive(Default)]
+pub struct Qemu {
+	arch: Arch,
+	/// Whether QEMU started the guest, see get_exit_code
+	booted: Cell<bool>,
+}
+
+impl Qemu {
+	fn connect_qmp(&self) -> Result<QmpClient, Box<dyn Error>> {
+		QmpClient::connect(&Path::new(consts::VMM_SOCKET_DIR).join(consts::QMP_SOCKET_NAME))
+	}
+}
+
+impl Vmm for Qemu {
+	fn get_args(
+		&mut self,
+		app: &str,
+		app_args: &[String],
+		netconf: &NetworkConfig,
+		vm_config: &VmConfig,
+		kvm: bool,
+		fds: &VmmFds,
+	) -> Vec<String> {
+		self.arch = get_app_arch(Path::new(app));
+		let kvm = if kvm && Arch::host() != Some(self.arch) {
+			warn!(
+				"Emulating {} hermit application without KVM on a {} host!",
+				self.arch.name(),
+				std::env::consts::ARCH
+			);
+			false
+		} else {
+			kvm
+		};
+
+		let kernel_path = get_loader_path(app, self.arch);
+		get_qemu_args(
+			kernel_path.to_str().unwrap(),
+			app,
+			netconf,
+			app_args,
+			vm_config,
+			self.arch,
+			kvm,
+			fds,
+		)
+	}
+
+	fn control_socket(&self) -> Option<&'static str> {
+		Some(consts::QMP_SOCKET_NAME)
+	}
+
+	fn power_down(&self) -> bool {
+		self.connect_qmp()
+			.and_then(|mut qmp| qmp.execute("system_powerdown"))
+			.is_ok()
+	}
+
+	fn uses_virtiofsd(&self, vm_config: &VmConfig) -> bool {
+		!vm_config.micro_vm
+	}
+
+	/// The isa-debug-exit device exits with the status `(code << 1) | 1`, where `code` is
+	/// the value the hermit application wrote to the device. Even statuses are QEMU's own
+	/// exit codes, e.g. 0 after a regular power down, and are passed through unchanged.
+	/// QEMU errors also use the status 1, so it is only translated to 0 if the guest booted.
+	/// On aarch64, the application exits through semihosting, which uses its exit code directly.
+	fn get_exit_code(&self, status: i32) -> i32 {
+		if self.arch == Arch::X86_64 && status & 1 == 1 && (status != 1 || self.booted.get()) {
+			status >> 1
+		} else {
+			status
+		}
+	}
+
+	/// QEMU boots on its own, but only answers on the QMP socket once it started the guest.
+	/// If it fails before, it exits on its own and get_exit_code reports the failure.
+	fn boot(&self) -> Result<(), Box<dyn Error>> {
+		self.booted.set(
+			self.connect_qmp()
+				.and_then(|mut qmp| qmp.execute("query-status"))
+				.is_ok(),
+		);
+		Ok(())
+	}
+}
+
+/// uhyve is a minimal hypervisor for hermit, which loads the application directly.
+/// It requires KVM and does not provide a network device.
+pub struct Uhyve;
+
+impl Vmm for Uhyve {
+	fn get_args(
+		&mut self,
+		app: &str,
+		app_args: &[String],
+		_netconf: &NetworkConfig,
+		vm_config: &VmConfig,
+		_kvm: bool,
+		_fds: &VmmFds,
+	) -> Vec<String> {
+		ensure_native_app("uhyve", get_app_arch(Path::new(app)));
+		let mut exec_args = vec![
+			String::from("uhyve"),
+			String::from("--memory"),
+			format!("{}MiB", vm_config.resources.memory),
+			String::from("--cpu-count"),
+			vm_config.resources.cpus.to_string(),
+			String::from("--"),
+			app.to_string(),
+		];
+		if let Some(application_args) = app_args.get(1..) {
+			exec_args.extend_from_slice(application_args);
+		}
+		exec_args
+	}
+}
+
+/// cloud-hypervisor is started with an API socket, through which it receives the
+/// configuration of the VM and the tap device.
+#[derive(Default)]
+pub struct CloudHypervisor {
+	vm_config: Value,
+	net_config: Option<Value>,
+	tap_fd: Option<i32>,
+}
+
+impl Vmm for CloudHypervisor {
+	fn get_args(
+		&mut self,
+		app: &str,
+		app_args: &[String],
+		netconf: &NetworkConfig,
+		vm_config: &VmConfig,
+		_kvm: bool,
+		fds: &VmmFds,
+	) -> Vec<String> {
+		ensure_native_app("cloud-hypervisor", get_app_arch(Path::new(app)));
+		self.vm_config = json!({
+			"cpus": {
+				"boot_vcpus": vm_config.resources.cpus,
+				"max_vcpus": vm_config.resources.cpus,
+			},
+			"memory": { "size": vm_config.resources.memory * 1024 * 1024 },
+			"payload": {
+				"kernel": get_loader_path(app, Arch::host().unwrap()),
+				"initramfs": app,
+				"cmdline": get_kernel_args(netconf, app_args, vm_config),
+			},
+			"serial": { "mode": "Tty" },
+			"console": { "mode": "Off" },
+		});
+		// The tap device is passed along with the request, as it is only open in this process
+		if let NetworkConfig::TapNetwork(network_config) = netconf {
+			self.net_config = Some(json!({ "id": "net0", "mac": network_config.mac }));
+			self.tap_fd = fds.tap;
+		}
+
+		vec![
+			String::from("cloud-hypervisor"),
+			String::from("--api-socket"),
+			format!("fd={}", fds.control.unwrap()),
+		]
+	}
+
+	fn control_socket(&self) -> Option<&'static str> {
+		Some(consts::CLOUD_HYPERVISOR_API_SOCKET_NAME)
+	}
+
+	fn boot(&self) -> Result<(), Box<dyn Error>> {
+		let mut api = ApiClient::connect(
+			&Path::new(consts::VMM_SOCKET_DIR).join(consts::CLOUD_HYPERVISOR_API_SOCKET_NAME),
+		)?;
+		api.put("vm.create", Some(&self.vm_config), &[])?;
+		if let Some(net_config) = &self.net_config {
+			api.put("vm.add-net", Some(net_config), &[self.tap_fd.unwrap()])?;
+		}
+		api.put("vm.boot", None, &[])
+	}
+}
+
+pub fn get_vmm(kind: VmmKind) -> Box<dyn Vmm> {
+	match kind {
+		VmmKind::Qemu => Box::<Qemu>::default(),
+		VmmKind::Uhyve => Box::new(Uhyve),
+		VmmKind::CloudHypervisor => Box::<CloudHypervisor>::default(),
+	}
+}
+
+/// The hermit-loader has to be located next to the application. A loader for a specific
+/// architecture (e.g. hermit-loader-aarch64) takes precedence over the generic hermit-loader.
+fn get_loader_path(app: &str, arch: Arch) -> PathBuf {
+	let app_root = Path::new(app)
+		.parent()
+		.expect("App path does not have a parent!");
+	let arch_loader = app_root.join(format!("hermit-loader-{}", arch.name()));
+	if arch_loader.exists() {
+		arch_loader
+	} else {
+		app_root.join("hermit-loader")
+	}
+}
+
+/// Command line of the hermit kernel with the network configuration and the application arguments
+fn get_kernel_args(netconf: &NetworkConfig, app_args: &[String], vm_config: &VmConfig) -> String {
+	let mut args_string = match netconf {
+		NetworkConfig::TapNetwork(network_config) => format!(
+			"-ip {} -gateway {} -mask {}",
+			network_config.ip, network_config.gateway, network_config.mask
+		),
+		NetworkConfig::UserNetwork(_) | NetworkConfig::None => "".to_string(),
+	};
+
+	if !vm_config.extra_kernel_args.is_empty() {
+		args_string = format!("{} {}", args_string, vm_config.extra_kernel_args.join(" "));
+	}
+	if let Some(application_args) = app_args.get(1..) {
+		args_string = format!("{} -- {}", args_string, application_args.join(" "));
+	}
+	args_string
+}
+
+#[allow(clippy::too_many_arguments)]
+fn get_qemu_args(
 	kernel: &str,
 	app: &str,
 	netconf: &NetworkConfig,
 	app_args: &[String],
-	micro_vm: bool,
+	vm_config: &VmConfig,
+	arch: Arch,
 	kvm: bool,
-	tap_fd: &Option<i32>,
+	fds: &VmmFds,
 ) -> Vec<String> {
+	let resources = &vm_config.resources;
+	let micro_vm = vm_config.micro_vm;
 	let mut exec_args: Vec<String> = vec![
-		"qemu-system-x86_64",
-		"-display",
-		"none",
-		"-smp",
-		"1",
-		"-m",
-		"1G",
-		"-serial",
-		"stdio",
-		"-device",
-		"isa-debug-exit,iobase=0xf4,iosize=0x04",
-		"-kernel",
-		kernel,
-		"-initrd",
-		app,
-	]
-	.iter()
-	.map(|s| s.to_string())
-	.collect();
+		format!("qemu-system-{}", arch.name()),
+		"-display".to_string(),
+		"none".to_string(),
+		"-smp".to_string(),
+		resources.cpus.to_string(),
+		"-m".to_string(),
+		format!("{}M", resources.memory),
+		"-serial".to_string(),
+		"stdio".to_string(),
+		"-kernel".to_string(),
+		kernel.to_string(),
+	];
+
+	match arch {
+		Arch::X86_64 => exec_args.append(
+			&mut [
+				"-device",
+				"isa-debug-exit,iobase=0xf4,iosize=0x04",
+				"-initrd",
+				app,
+			]
+			.iter()
+			.map(|s| s.to_string())
+			.collect(),
+		),
+		// The loader expects the application at a fixed address and exits through semihosting
+		Arch::Aarch64 => exec_args.append(
+			&mut [
+				"-machine",
+				"virt,gic-version=3",
+				"-semihosting",
+				"-device",
+				&format!("guest-loader,addr=0x48000000,initrd={}", app),
+			]
+			.iter()
+			.map(|s| s.to_string())
+			.collect(),
+		),
+	}
+
+	// Used by runh pause/resume to stop the vCPUs
+	exec_args.push("-chardev".to_string());
+	exec_args.push(format!(
+		"socket,id=qmp,fd={},server=on,wait=off",
+		fds.control.unwrap()
+	));
+	exec_args.push("-mon".to_string());
+	exec_args.push("chardev=qmp,mode=control".to_string());
 
 	if kvm {
 		exec_args.append(
@@ -81,40 +563,45 @@ pub fn get_qemu_args(
 				.collect(),
 		);
 	} else {
-		exec_args.append(
-			&mut [
-				"-cpu",
-				"qemu64,apic,fsgsbase,rdtscp,xsave,xsaveopt,fxsr,rdrand",
-			]
-			.iter()
-			.map(|s| s.to_string())
-			.collect(),
+		exec_args.push("-cpu".to_string());
+		exec_args.push(
+			match arch {
+				Arch::X86_64 => "qemu64,apic,fsgsbase,rdtscp,xsave,xsaveopt,fxsr,rdrand",
+				Arch::Aarch64 => "cortex-a72",
+			}
+			.to_string(),
 		);
 	}
 
 	if micro_vm {
-		exec_args.append(
-			&mut [
-				"-M",
-				"microvm,x-option-roms=off,pit=off,pic=off,rtc=on,auto-kernel-cmdline=off,acpi=off",
-				"-global",
-				"virtio-mmio.force-legacy=off",
-				"-nodefaults",
-				"-no-user-config",
-			]
-			.iter()
-			.map(|s| s.to_string())
-			.collect(),
-		);
+		// The virt machine of aarch64 is already minimal and provides virtio-mmio devices
+		if arch == Arch::X86_64 {
+			exec_args.append(
+				&mut [
+					"-M",
+					"microvm,x-option-roms=off,pit=off,pic=off,rtc=on,auto-kernel-cmdline=off,acpi=off",
+					"-nodefaults",
+					"-no-user-config",
+				]
+				.iter()
+				.map(|s| s.to_string())
+				.collect(),
+			);
+		}
+		exec_args.push("-global".to_string());
+		exec_args.push("virtio-mmio.force-legacy=off".to_string());
 	} else {
 		exec_args.append(
 			&mut [
 				"-chardev",
-				"socket,id=char0,path=/run/vhostqemu",
+				&format!("socket,id=char0,path={}", consts::VIRTIOFSD_SOCKET_PATH),
 				"-device",
 				"vhost-user-fs-pci,queue-size=1024,chardev=char0,tag=root",
 				"-object",
-				"memory-backend-file,id=mem,size=1G,mem-path=/dev/shm,share=on",
+				&format!(
+					"memory-backend-file,id=mem,size={}M,mem-path=/dev/shm,share=on",
+					resources.memory
+				),
 				"-numa",
 				"node,memdev=mem",
 			]
@@ -124,10 +611,10 @@ pub fn get_qemu_args(
 		);
 	}
 
-	let mut args_string = match netconf {
+	match netconf {
 		NetworkConfig::TapNetwork(network_config) => {
 			exec_args.push("-netdev".to_string());
-			exec_args.push(format!("tap,id=net0,fd={}", tap_fd.unwrap()));
+			exec_args.push(format!("tap,id=net0,fd={}", fds.tap.unwrap()));
 			exec_args.push("-device".to_string());
 			exec_args.push(if micro_vm {
 				format!("virtio-net-device,netdev=net0,mac={}", network_config.mac)
@@ -137,36 +624,127 @@ pub fn get_qemu_args(
 					network_config.mac
 				)
 			});
-			exec_args.push("-append".to_string());
-
-			let args_string = format!(
-				"-ip {} -gateway {} -mask {}",
-				network_config.ip, network_config.gateway, network_config.mask
-			);
-
-			args_string
 		}
 		NetworkConfig::UserNetwork(user_port) => {
+			let hostfwd = user_port
+				.map(|user_port| format!("hostfwd=tcp::{user_port}-:{user_port},"))
+				.unwrap_or_default();
 			exec_args.push("-netdev".to_string());
 			exec_args.push(format!(
-				"user,id=u1,hostfwd=tcp::{user_port}-:{user_port},net=192.168.76.0/24,dhcpstart=192.168.76.9"
+				"user,id=u1,{hostfwd}net=192.168.76.0/24,dhcpstart=192.168.76.9"
 			));
 			exec_args.push("-device".to_string());
 			exec_args.push("virtio-net-pci,netdev=u1,disable-legacy=on".to_string());
-			exec_args.push("-append".to_string());
-
-			"".to_string()
-		}
-		NetworkConfig::None => {
-			exec_args.push("-append".to_string());
-			"".to_string()
 		}
+		NetworkConfig::None => {}
 	};
 
-	if let Some(application_args) = app_args.get(1..) {
-		args_string = format!("{} -- {}", args_string, application_args.join(" "));
-	}
-	exec_args.push(args_string);
+	exec_args.push("-append".to_string());
+	exec_args.push(get_kernel_args(netconf, app_args, vm_config));
 
 	exec_args
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use oci_spec::runtime::{Linux, LinuxCpu, LinuxMemory, LinuxMemoryBuilder, LinuxResources};
+
+	fn spec_with_resources(
+		quota: Option<i64>,
+		period: Option<u64>,
+		cpus: Option<&str>,
+		memory_limit: Option<i64>,
+	) -> Spec {
+		let mut cpu = LinuxCpu::default();
+		cpu.set_quota(quota);
+		cpu.set_period(period);
+		cpu.set_cpus(cpus.map(String::from));
+		let memory = match memory_limit {
+			Some(limit) => LinuxMemoryBuilder::default().limit(limit).build().unwrap(),
+			None => LinuxMemory::default(),
+		};
+		let mut resources = LinuxResources::default();
+		resources.set_cpu(Some(cpu));
+		resources.set_memory(Some(memory));
+		let mut linux = Linux::default();
+		linux.set_resources(Some(resources));
+		let mut spec = Spec::default();
+		spec.set_linux(Some(linux));
+		spec
+	}
+
+	#[test]
+	fn count_cpuset_cpus_counts_ranges_and_single_cpus() {
+		assert_eq!(count_cpuset_cpus(""), Some(0));
+		assert_eq!(count_cpuset_cpus("0-3,8"), Some(5));
+		assert_eq!(count_cpuset_cpus(" 2 , 4-5 "), Some(3));
+		assert_eq!(count_cpuset_cpus("3-1"), None);
+		assert_eq!(count_cpuset_cpus("a-b"), None);
+	}
+
+	#[test]
+	fn vm_resources_default_without_limits() {
+		let resources =
+			get_vm_resources(&spec_with_resources(None, None, None, None), None).unwrap();
+		assert_eq!(resources.cpus, DEFAULT_VM_CPUS);
+		assert_eq!(resources.memory, DEFAULT_VM_MEMORY);
+	}
+
+	#[test]
+	fn vm_cpus_from_quota_without_period() {
+		// The default period of 100ms applies and partial CPUs are rounded up
+		let spec = spec_with_resources(Some(250000), None, None, None);
+		assert_eq!(get_vm_resources(&spec, None).unwrap().cpus, 3);
+	}
+
+	#[test]
+	fn vm_cpus_are_limited_by_quota_and_cpuset() {
+		let spec = spec_with_resources(Some(400000), Some(100000), Some("0-1"), None);
+		assert_eq!(get_vm_resources(&spec, None).unwrap().cpus, 2);
+		let spec = spec_with_resources(None, None, Some("0-3,8"), None);
+		assert_eq!(get_vm_resources(&spec, None).unwrap().cpus, 5);
+	}
+
+	#[test]
+	fn vm_memory_from_limit_minus_overhead() {
+		let spec = spec_with_resources(None, None, None, Some(512 * 1024 * 1024));
+		assert_eq!(
+			get_vm_resources(&spec, None).unwrap().memory,
+			512 - DEFAULT_VMM_MEMORY_OVERHEAD
+		);
+		assert_eq!(get_vm_resources(&spec, Some(64)).unwrap().memory, 448);
+	}
+
+	#[test]
+	fn vm_memory_limit_below_overhead_is_rejected() {
+		let spec = spec_with_resources(None, None, None, Some(192 * 1024 * 1024));
+		assert!(get_vm_resources(&spec, None).is_err());
+		assert_eq!(get_vm_resources(&spec, Some(127)).unwrap().memory, 65);
+	}
+
+	#[test]
+	fn qemu_exit_code_of_isa_debug_exit() {
+		let qemu = Qemu {
+			booted: Cell::new(true),
+			..Default::default()
+		};
+		assert_eq!(qemu.get_exit_code((3 << 1) | 1), 3);
+		assert_eq!(qemu.get_exit_code(1), 0);
+		assert_eq!(qemu.get_exit_code(0), 0);
+		assert_eq!(qemu.get_exit_code(2), 2);
+
+		let qemu = Qemu {
+			arch: Arch::Aarch64,
+			booted: Cell::new(true),
+		};
+		assert_eq!(qemu.get_exit_code(3), 3);
+	}
+
+	#[test]
+	fn qemu_error_before_boot_is_a_failure() {
+		let qemu = Qemu::default();
+		assert_eq!(qemu.get_exit_code(1), 1);
+		assert_eq!(qemu.get_exit_code((3 << 1) | 1), 3);
+	}
+}
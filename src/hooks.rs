@@ -0,0 +1,142 @@
+use crate::state::State;
+use oci_spec::runtime;
+use std::io::{Read, Write};
+use std::os::unix::process::CommandExt;
+use std::time::{Duration, Instant};
+
+fn run_hook(hook: &runtime::Hook, state_json: &str) -> Result<(), String> {
+	if let Some(timeout) = hook.timeout().filter(|timeout| *timeout <= 0) {
+		return Err(format!(
+			"Hook {:?} has an invalid timeout of {timeout} seconds",
+			hook.path()
+		));
+	}
+
+	let mut cmd = std::process::Command::new(hook.path());
+	if let Some(args) = &hook.args() {
+		if !args.is_empty() {
+			cmd.arg0(&args[0]);
+		}
+		if args.len() > 1 {
+			cmd.args(&args[1..]);
+		}
+	}
+	// Hooks only get the environment given in the spec
+	cmd.env_clear();
+	if let Some(env) = &hook.env() {
+		for var in env {
+			let (name, value) = var
+				.split_once('=')
+				.ok_or_else(|| format!("Could not parse environment variable: {var}"))?;
+			cmd.env(name, value);
+		}
+	}
+	cmd.stdout(std::process::Stdio::null());
+	cmd.stderr(std::process::Stdio::piped());
+	cmd.stdin(std::process::Stdio::piped());
+	let mut child = cmd
+		.spawn()
+		.map_err(|err| format!("Unable to spawn hook process {:?}: {err}", hook.path()))?;
+
+	let deadline = hook
+		.timeout()
+		.map(|timeout| Instant::now() + Duration::from_secs(timeout as u64));
+
+	// A hook might not read its stdin at all, so the state is written concurrently to not
+	// block beyond the timeout, and a failed write is not an error
+	let mut stdin = child.stdin.take().unwrap();
+	let state_json = state_json.to_owned();
+	std::thread::spawn(move || {
+		let _ = write!(stdin, "{state_json}");
+	});
+
+	// Read stderr concurrently, so that the hook does not block on a full pipe
+	let mut stderr = child.stderr.take().unwrap();
+	let stderr_reader = std::thread::spawn(move || {
+		let mut output = String::new();
+		let _ = stderr.read_to_string(&mut output);
+		output
+	});
+
+	let status = loop {
+		if let Some(status) = child
+			.try_wait()
+			.map_err(|err| format!("Could not wait for hook {:?}: {err}", hook.path()))?
+		{
+			break status;
+		}
+		if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+			let _ = child.kill();
+			let _ = child.wait();
+			return Err(format!(
+				"Hook {:?} did not finish within its timeout of {} seconds and was killed",
+				hook.path(),
+				hook.timeout().unwrap()
+			));
+		}
+		std::thread::sleep(Duration::from_millis(10));
+	};
+
+	let stderr_output = stderr_reader.join().unwrap_or_default();
+	if status.success() {
+		Ok(())
+	} else {
+		Err(format!(
+			"Hook {:?} returned exit status {}. Stderr: {}",
+			hook.path(),
+			status,
+			stderr_output
+		))
+	}
+}
+
+/// Run the given hooks in order with the container state on stdin. Stops at the first failing hook.
+pub fn run_hooks(
+	hooks: &Option<Vec<runtime::Hook>>,
+	state: &State,
+	kind: &str,
+) -> Result<(), String> {
+	let Some(hooks) = hooks else {
+		return Ok(());
+	};
+
+	let state_json = serde_json::to_string(state).unwrap();
+	for hook in hooks {
+		debug!("Running {} hook {:?}", kind, hook.path());
+		run_hook(hook, &state_json).map_err(|err| format!("{kind} hook failed: {err}"))?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use oci_spec::runtime::HookBuilder;
+
+	fn hook(path: &str, args: &[&str], timeout: Option<i64>) -> runtime::Hook {
+		let mut hook = HookBuilder::default()
+			.path(path)
+			.args(args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+			.build()
+			.unwrap();
+		hook.set_timeout(timeout);
+		hook
+	}
+
+	#[test]
+	fn non_positive_timeouts_are_rejected() {
+		assert!(run_hook(&hook("/bin/true", &["true"], Some(0)), "{}").is_err());
+		assert!(run_hook(&hook("/bin/true", &["true"], Some(-1)), "{}").is_err());
+		assert!(run_hook(&hook("/bin/true", &["true"], Some(1)), "{}").is_ok());
+	}
+
+	#[test]
+	fn timeout_applies_to_hooks_not_reading_stdin() {
+		// The state does not fit into the pipe buffer, so writing it blocks
+		let state_json = "x".repeat(1024 * 1024);
+		let start = Instant::now();
+		let result = run_hook(&hook("/bin/sleep", &["sleep", "10"], Some(1)), &state_json);
+		assert!(result.is_err());
+		assert!(start.elapsed() < Duration::from_secs(5));
+	}
+}
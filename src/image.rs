@@ -0,0 +1,374 @@
+use crate::hermit::Arch;
+use crate::paths;
+use goblin::container::Ctx;
+use goblin::elf;
+use goblin::elf::header::{EI_CLASS, EI_OSABI, ELFCLASS32, ELFCLASS64};
+use oci_spec::runtime::Spec;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Search path of the container process, if its environment does not define PATH
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// OS ABI of hermit applications in the ELF identification
+const ELFOSABI_STANDALONE: u8 = 0xFF;
+/// Owner of the ELF notes of the hermit kernel
+const HERMIT_NOTE_NAME: &str = "HERMIT";
+/// Type of the ELF note that carries the version of the entry ABI of the hermit kernel
+const NT_HERMIT_ENTRY_VERSION: u32 = 0x5a00;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfClass {
+	Elf32,
+	Elf64,
+}
+
+/// ELF note of the hermit kernel that is linked into an application
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HermitNote {
+	/// Version of the entry ABI between the loader and the kernel
+	pub entry_version: String,
+}
+
+/// Properties of the executable of a container
+#[derive(Debug)]
+pub struct ExecutableInfo {
+	/// Location of the executable on the host
+	pub path: PathBuf,
+	pub class: ElfClass,
+	/// Raw e_machine of the ELF header
+	pub machine: u16,
+	/// Architecture, if hermit VMs can be started for it
+	pub arch: Option<Arch>,
+	pub os_abi: u8,
+	pub hermit_note: Option<HermitNote>,
+}
+
+impl ExecutableInfo {
+	/// Hermit only supports 64-bit applications, which are marked by their OS ABI or the
+	/// ELF note of the kernel.
+	pub fn is_hermit_app(&self) -> bool {
+		self.class == ElfClass::Elf64
+			&& (self.os_abi == ELFOSABI_STANDALONE || self.hermit_note.is_some())
+	}
+}
+
+#[derive(Debug)]
+pub enum InspectError {
+	/// args[0] could not be found in the rootfs
+	NotFound(PathBuf),
+	Unreadable(PathBuf, std::io::Error),
+	/// The executable is not an ELF file, e.g. a script
+	NotElf(PathBuf, String),
+}
+
+impl fmt::Display for InspectError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			InspectError::NotFound(path) => write!(f, "Could not find executable {:?}", path),
+			InspectError::Unreadable(path, err) => {
+				write!(f, "Could not read executable at {:?}: {}", path, err)
+			}
+			InspectError::NotElf(path, err) => {
+				write!(f, "Executable at {:?} is not an ELF file: {}", path, err)
+			}
+		}
+	}
+}
+
+fn get_process_path_env(spec: &Spec) -> String {
+	spec.process()
+		.as_ref()
+		.and_then(|process| process.env().as_ref())
+		.and_then(|env| env.iter().find_map(|var| var.strip_prefix("PATH=")))
+		.unwrap_or(DEFAULT_PATH)
+		.to_string()
+}
+
+/// Locate args[0] of the container process in the rootfs, like execvp inside the container would.
+/// Symlinks are resolved within the rootfs by find_in_path.
+pub fn resolve_executable(spec: &Spec, rootfs: &Path) -> Result<PathBuf, InspectError> {
+	let process = spec.process().as_ref().unwrap();
+	let exec_path = PathBuf::from(
+		process
+			.args()
+			.as_ref()
+			.and_then(|args| args.first())
+			.expect("Container spec does not contain any args!"),
+	);
+
+	// Relative paths with a slash are not searched in PATH, but relative to the cwd
+	let search_path = if exec_path.components().count() > 1 {
+		process.cwd().join(&exec_path)
+	} else {
+		exec_path.clone()
+	};
+	paths::find_in_search_path(
+		search_path,
+		Some(&rootfs.to_path_buf()),
+		&get_process_path_env(spec),
+	)
+	.ok_or(InspectError::NotFound(exec_path))
+}
+
+/// Read `size` bytes at `offset`. The buffer only grows with the data that is actually read,
+/// so that bogus sizes in the ELF headers do not lead to huge allocations.
+fn read_at(mut file: &File, offset: u64, size: u64) -> io::Result<Vec<u8>> {
+	file.seek(SeekFrom::Start(offset))?;
+	let mut buffer = Vec::new();
+	file.take(size).read_to_end(&mut buffer)?;
+	if (buffer.len() as u64) < size {
+		return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+	}
+	Ok(buffer)
+}
+
+fn parse_hermit_note(note: &elf::note::Note) -> HermitNote {
+	let desc = note
+		.desc
+		.split(|byte| *byte == 0)
+		.next()
+		.unwrap_or_default();
+	// Older kernels only store a version number instead of a string
+	let entry_version = match std::str::from_utf8(desc) {
+		Ok(version) if desc.len() > 1 => version.to_string(),
+		_ => note
+			.desc
+			.iter()
+			.map(u8::to_string)
+			.collect::<Vec<_>>()
+			.join("."),
+	};
+	HermitNote { entry_version }
+}
+
+/// Search the note segments for the entry version note of the hermit kernel.
+/// Only the program headers and the note segments are read from the file.
+fn get_hermit_note(
+	path: &Path,
+	file: &File,
+	header: &elf::Header,
+) -> Result<Option<HermitNote>, InspectError> {
+	let not_elf =
+		|err: goblin::error::Error| InspectError::NotElf(path.to_path_buf(), err.to_string());
+	let read_error = |err: io::Error| {
+		if err.kind() == io::ErrorKind::UnexpectedEof {
+			InspectError::NotElf(path.to_path_buf(), String::from("File is truncated"))
+		} else {
+			InspectError::Unreadable(path.to_path_buf(), err)
+		}
+	};
+
+	let ctx = Ctx::new(
+		header.container().map_err(not_elf)?,
+		header.endianness().map_err(not_elf)?,
+	);
+	let phdr_table = read_at(
+		file,
+		header.e_phoff,
+		header.e_phnum as u64 * header.e_phentsize as u64,
+	)
+	.map_err(read_error)?;
+	let program_headers =
+		elf::ProgramHeader::parse(&phdr_table, 0, header.e_phnum as usize, ctx).map_err(not_elf)?;
+
+	for phdr in program_headers
+		.iter()
+		.filter(|phdr| phdr.p_type == elf::program_header::PT_NOTE)
+	{
+		let data = read_at(file, phdr.p_offset, phdr.p_filesz).map_err(read_error)?;
+		let mut notes = elf::note::NoteDataIterator {
+			data: &data,
+			size: data.len(),
+			offset: 0,
+			ctx: (phdr.p_align as usize, ctx),
+		};
+		let hermit_note = notes.find_map(|note| {
+			note.ok().filter(|note| {
+				note.name.trim_end_matches('\0') == HERMIT_NOTE_NAME
+					&& note.n_type == NT_HERMIT_ENTRY_VERSION
+			})
+		});
+		if let Some(note) = hermit_note {
+			return Ok(Some(parse_hermit_note(&note)));
+		}
+	}
+	Ok(None)
+}
+
+/// Read the ELF header and notes of an executable.
+pub fn inspect_file(path: &Path) -> Result<ExecutableInfo, InspectError> {
+	let file = File::open(path).map_err(|err| InspectError::Unreadable(path.to_path_buf(), err))?;
+	let mut header_buffer = Vec::new();
+	(&file)
+		.take(elf::header::header64::SIZEOF_EHDR as u64)
+		.read_to_end(&mut header_buffer)
+		.map_err(|err| InspectError::Unreadable(path.to_path_buf(), err))?;
+	let header = elf::Elf::parse_header(&header_buffer)
+		.map_err(|err| InspectError::NotElf(path.to_path_buf(), err.to_string()))?;
+
+	let class = match header.e_ident[EI_CLASS] {
+		ELFCLASS32 => ElfClass::Elf32,
+		ELFCLASS64 => ElfClass::Elf64,
+		class => {
+			return Err(InspectError::NotElf(
+				path.to_path_buf(),
+				format!("Invalid ELF class {}", class),
+			))
+		}
+	};
+	let arch = match header.e_machine {
+		elf::header::EM_X86_64 => Some(Arch::X86_64),
+		elf::header::EM_AARCH64 => Some(Arch::Aarch64),
+		_ => None,
+	};
+
+	let info = ExecutableInfo {
+		path: path.to_path_buf(),
+		class,
+		machine: header.e_machine,
+		arch,
+		os_abi: header.e_ident[EI_OSABI],
+		hermit_note: get_hermit_note(path, &file, &header)?,
+	};
+	debug!("Inspected executable: {:?}", info);
+	Ok(info)
+}
+
+/// Resolve and inspect the executable of the container process.
+pub fn inspect_executable(spec: &Spec, rootfs: &Path) -> Result<ExecutableInfo, InspectError> {
+	inspect_file(&resolve_executable(spec, rootfs)?)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Build a little-endian ELF64 executable with an optional note segment.
+	fn build_elf(machine: u16, os_abi: u8, note: Option<(&str, u32, &[u8])>) -> Vec<u8> {
+		let mut elf = vec![0x7f, b'E', b'L', b'F', ELFCLASS64, 1, 1, os_abi];
+		elf.resize(16, 0);
+		elf.extend_from_slice(&2u16.to_le_bytes()); // e_type
+		elf.extend_from_slice(&machine.to_le_bytes());
+		elf.extend_from_slice(&1u32.to_le_bytes()); // e_version
+		elf.extend_from_slice(&0u64.to_le_bytes()); // e_entry
+		elf.extend_from_slice(&64u64.to_le_bytes()); // e_phoff
+		elf.extend_from_slice(&0u64.to_le_bytes()); // e_shoff
+		elf.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+		elf.extend_from_slice(&64u16.to_le_bytes()); // e_ehsize
+		elf.extend_from_slice(&56u16.to_le_bytes()); // e_phentsize
+		elf.extend_from_slice(&(note.is_some() as u16).to_le_bytes()); // e_phnum
+		elf.extend_from_slice(&[0; 6]); // e_shentsize, e_shnum, e_shstrndx
+
+		if let Some((name, n_type, desc)) = note {
+			let pad = |data: &mut Vec<u8>| data.resize(data.len().div_ceil(4) * 4, 0);
+			let mut data = Vec::new();
+			data.extend_from_slice(&(name.len() as u32 + 1).to_le_bytes());
+			data.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+			data.extend_from_slice(&n_type.to_le_bytes());
+			data.extend_from_slice(name.as_bytes());
+			data.push(0);
+			pad(&mut data);
+			data.extend_from_slice(desc);
+			pad(&mut data);
+
+			elf.extend_from_slice(&elf::program_header::PT_NOTE.to_le_bytes());
+			elf.extend_from_slice(&4u32.to_le_bytes()); // p_flags
+			elf.extend_from_slice(&120u64.to_le_bytes()); // p_offset
+			elf.extend_from_slice(&[0; 16]); // p_vaddr, p_paddr
+			elf.extend_from_slice(&(data.len() as u64).to_le_bytes()); // p_filesz
+			elf.extend_from_slice(&(data.len() as u64).to_le_bytes()); // p_memsz
+			elf.extend_from_slice(&4u64.to_le_bytes()); // p_align
+			elf.extend_from_slice(&data);
+		}
+		elf
+	}
+
+	fn inspect_bytes(name: &str, bytes: &[u8]) -> Result<ExecutableInfo, InspectError> {
+		let path = std::env::temp_dir().join(format!("runh-image-{}-{}", std::process::id(), name));
+		std::fs::write(&path, bytes).unwrap();
+		let info = inspect_file(&path);
+		std::fs::remove_file(&path).unwrap();
+		info
+	}
+
+	#[test]
+	fn detects_the_architecture() {
+		let info = inspect_bytes("x86_64", &build_elf(elf::header::EM_X86_64, 0, None)).unwrap();
+		assert_eq!(info.class, ElfClass::Elf64);
+		assert_eq!(info.arch, Some(Arch::X86_64));
+
+		let info = inspect_bytes("aarch64", &build_elf(elf::header::EM_AARCH64, 0, None)).unwrap();
+		assert_eq!(info.arch, Some(Arch::Aarch64));
+
+		let info = inspect_bytes("riscv", &build_elf(elf::header::EM_RISCV, 0, None)).unwrap();
+		assert_eq!(info.machine, elf::header::EM_RISCV);
+		assert_eq!(info.arch, None);
+	}
+
+	#[test]
+	fn detects_hermit_apps_by_os_abi() {
+		let info = inspect_bytes(
+			"standalone",
+			&build_elf(elf::header::EM_X86_64, ELFOSABI_STANDALONE, None),
+		)
+		.unwrap();
+		assert!(info.is_hermit_app());
+		assert_eq!(info.hermit_note, None);
+
+		let info = inspect_bytes("linux", &build_elf(elf::header::EM_X86_64, 0, None)).unwrap();
+		assert!(!info.is_hermit_app());
+	}
+
+	#[test]
+	fn detects_hermit_apps_by_note() {
+		let elf = build_elf(
+			elf::header::EM_X86_64,
+			0,
+			Some((HERMIT_NOTE_NAME, NT_HERMIT_ENTRY_VERSION, &[1])),
+		);
+		let info = inspect_bytes("note", &elf).unwrap();
+		assert!(info.is_hermit_app());
+		assert_eq!(info.hermit_note.unwrap().entry_version, "1");
+
+		let elf = build_elf(
+			elf::header::EM_AARCH64,
+			0,
+			Some((HERMIT_NOTE_NAME, NT_HERMIT_ENTRY_VERSION, b"0.1.0\0")),
+		);
+		let info = inspect_bytes("note-string", &elf).unwrap();
+		assert_eq!(info.hermit_note.unwrap().entry_version, "0.1.0");
+	}
+
+	#[test]
+	fn ignores_foreign_notes() {
+		let elf = build_elf(
+			elf::header::EM_X86_64,
+			0,
+			Some(("GNU", NT_HERMIT_ENTRY_VERSION, &[1])),
+		);
+		let info = inspect_bytes("gnu-note", &elf).unwrap();
+		assert_eq!(info.hermit_note, None);
+		assert!(!info.is_hermit_app());
+	}
+
+	#[test]
+	fn rejects_scripts_and_truncated_files() {
+		assert!(matches!(
+			inspect_bytes("script", b"#!/bin/sh\necho hello\n"),
+			Err(InspectError::NotElf(..))
+		));
+
+		let elf = build_elf(
+			elf::header::EM_X86_64,
+			0,
+			Some((HERMIT_NOTE_NAME, NT_HERMIT_ENTRY_VERSION, &[1])),
+		);
+		assert!(matches!(
+			inspect_bytes("truncated", &elf[..100]),
+			Err(InspectError::NotElf(..))
+		));
+	}
+}
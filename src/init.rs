@@ -1,9 +1,11 @@
 use std::fs::OpenOptions;
 use std::io::{Read, Write};
 use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::unix::net::{UnixListener, UnixStream};
 use std::os::unix::prelude::{IntoRawFd, OpenOptionsExt};
 use std::os::unix::process::CommandExt;
-use std::path::PathBuf;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
 use std::{
 	env,
 	fs::File,
@@ -11,11 +13,15 @@ use std::{
 };
 
 use crate::hermit::NetworkConfig;
-use crate::{console, devices, hermit, mounts};
-use crate::{flags, paths, rootfs};
-use crate::{namespaces, network};
+use crate::state::State;
+use crate::vm_config::{NetworkMode, VmConfig};
+use crate::{apparmor, capabilities, cloned_binary, console, devices, hermit, hooks, mounts};
+use crate::{flags, image, limits, paths, rootfs, user};
+use crate::{namespaces, network, seccomp, supervisor};
 use capctl::prctl;
 use command_fds::CommandFdExt;
+use nix::errno::Errno;
+use nix::fcntl::{FcntlArg, FdFlag};
 use nix::sched::{self, CloneFlags};
 use nix::unistd::{Gid, Pid, Uid};
 use oci_spec::runtime;
@@ -68,7 +74,10 @@ pub fn init_container() {
 		.parse()
 		.expect("RUNH_INITPIPE was not an integer!");
 
-	//TODO: Ensure we are in a cloned binary (prevent CVE-2019-5736)
+	// runh create starts us from a sealed copy of the binary (prevent CVE-2019-5736)
+	if !cloned_binary::is_cloned_binary() {
+		panic!("runh init is not running from a sealed copy of the runh binary!");
+	}
 
 	//Detect hermit container
 	let is_hermit_container: bool = env::var("RUNH_HERMIT_CONTAINER")
@@ -184,23 +193,43 @@ fn init_stage_parent(args: SetupArgs) -> isize {
 		namespaces::join_namespaces(namespaces)
 	}
 
-	//TODO: Unshare user namespace if requested (needs additional clone)
+	// runc needs an additional clone here, because the Go runtime is multi-threaded.
+	// As runh init is still single-threaded at this point, unshare is sufficient.
 	if args.config.cloneflags.contains(CloneFlags::CLONE_NEWUSER) {
-		unimplemented!("User namespaces are currently not supported by runh!")
-	}
+		debug!("unshare user namespace");
+		nix::sched::unshare(CloneFlags::CLONE_NEWUSER).expect("could not unshare user namespace!");
 
-	//TODO: Let parent setup uidmap/gidmap if a user namespace was joined
+		// runh create has to access /proc/<pid> of this process to write the id mappings
+		prctl::set_dumpable(true).expect("Could not set process as dumpable!");
+		let mut init_pipe = unsafe { File::from_raw_fd(args.init_pipe) };
+		init_pipe
+			.write_all(&[crate::consts::INIT_REQ_ID_MAPPINGS])
+			.expect("Unable to write to init-pipe!");
+		let mut sig_buffer = [0u8];
+		init_pipe
+			.read_exact(&mut sig_buffer)
+			.expect("Could not read from init pipe!");
+		if sig_buffer[0] != crate::consts::CREATE_ACK_ID_MAPPINGS {
+			panic!(
+				"Received invalid signal from runh create! Expected {:x}, got {:x}",
+				crate::consts::CREATE_ACK_ID_MAPPINGS,
+				sig_buffer[0]
+			);
+		}
+		let _ = init_pipe.into_raw_fd();
+		prctl::set_dumpable(false).expect("Could not set process as non-dumpable!");
+	}
 
+	nix::unistd::setresgid(Gid::from_raw(0), Gid::from_raw(0), Gid::from_raw(0))
+		.expect("could not become root group in user namespace!");
 	nix::unistd::setresuid(Uid::from_raw(0), Uid::from_raw(0), Uid::from_raw(0))
 		.expect("could not become root in user namespace!");
 
-	// Unshare all other namespaces (except cgroup)
-	debug!(
-		"unshare namespaces with cloneflags {:?}",
-		args.config.cloneflags
-	);
+	// Unshare all other namespaces (except user and cgroup)
 	let mut flags = args.config.cloneflags;
+	flags.remove(CloneFlags::CLONE_NEWUSER);
 	flags.remove(CloneFlags::CLONE_NEWCGROUP);
+	debug!("unshare namespaces with cloneflags {:?}", flags);
 	nix::sched::unshare(flags).expect("could not unshare non-user namespaces!");
 
 	// Fork again into new PID-Namespace and send PID to parent
@@ -231,20 +260,69 @@ fn init_stage_child(args: SetupArgs) -> ! {
 	let _ = prctl::set_name("runh:INIT");
 	debug!("Welcome to the container! This is PID {}", Pid::this());
 
-	// Set SID, UID, GID
-	let _ = nix::unistd::setsid().expect("Could not set session ID");
-	nix::unistd::setuid(Uid::from_raw(0)).expect("Could not set user ID");
-	nix::unistd::setgid(Gid::from_raw(0)).expect("Could not set group ID");
+	// Wait until runh create has moved us into the container cgroup
+	let mut init_pipe = unsafe { File::from_raw_fd(args.init_pipe) };
+	let mut sig_buffer = [0u8];
+	init_pipe
+		.read_exact(&mut sig_buffer)
+		.expect("Could not read from init pipe!");
+	if sig_buffer[0] != crate::consts::CREATE_ACK_CGROUPS {
+		panic!(
+			"Received invalid signal from runh create! Expected {:x}, got {:x}",
+			crate::consts::CREATE_ACK_CGROUPS,
+			sig_buffer[0]
+		);
+	}
 
-	// TODO: Call setgroups if !is_rootless_euid && is_setgroup (?)
+	// Set SID and become root of the container until the setup is done
+	let _ = nix::unistd::setsid().expect("Could not set session ID");
+	user::switch_user(&runtime::User::default());
 
 	// Unshare Cgroup namespace if requested to
 	if args.config.cloneflags.contains(CloneFlags::CLONE_NEWCGROUP) {
-		// TODO: Synchronize with runh create for cgroup setup
 		nix::sched::unshare(CloneFlags::CLONE_NEWCGROUP)
 			.expect("could not unshare cgroups namespace!");
 	}
 
+	let rootless: bool = env::var("RUNH_ROOTLESS")
+		.unwrap_or_else(|_| "false".to_string())
+		.parse()
+		.expect("RUNH_ROOTLESS was not a boolean value!");
+	let rootfs_overlay = env::var("RUNH_ROOTFS_OVERLAY")
+		.ok()
+		.filter(|data| !data.is_empty());
+	let vmm_socket_dir = env::var("RUNH_VMM_SOCKET_DIR").ok();
+	let mut seccomp_listener = env::var("RUNH_SECCOMP_LISTENER")
+		.ok()
+		.filter(|fd| !fd.is_empty())
+		.map(|fd| {
+			let fd: RawFd = fd
+				.parse()
+				.expect("RUNH_SECCOMP_LISTENER was not an integer!");
+			// The connection to the seccomp agent must not leak into the container process
+			nix::fcntl::fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))
+				.expect("Could not set FD_CLOEXEC on seccomp listener!");
+			unsafe { UnixStream::from_raw_fd(fd) }
+		});
+
+	// Set rlimits and OOM score while we still have CAP_SYS_RESOURCE
+	if let Some(process) = args.config.spec.process() {
+		if let Some(rlimits) = process.rlimits() {
+			limits::set_rlimits(rlimits);
+		}
+		if let Some(oom_score_adj) = process.oom_score_adj() {
+			match limits::set_oom_score_adj(oom_score_adj) {
+				Ok(_) => {}
+				// Lowering the OOM score requires CAP_SYS_RESOURCE in the initial user namespace
+				Err(err) if rootless => warn!(
+					"Could not set oom_score_adj to {} in rootless mode, skipping: {}",
+					oom_score_adj, err
+				),
+				Err(err) => panic!("Could not set oom_score_adj to {}: {}", oom_score_adj, err),
+			}
+		}
+	}
+
 	// In runc's case, this is the point where control is transferred back to the go runtime
 	debug!("Read config from spec file");
 	let fifo_fd: i32 = env::var("RUNH_FIFOFD")
@@ -332,6 +410,17 @@ fn init_stage_child(args: SetupArgs) -> ! {
 
 	//Mount root file system
 	rootfs::mount_rootfs(&args.config.spec, &rootfs_path);
+	if let Some(overlay_data) = rootfs_overlay {
+		debug!("Mounting rootfs overlay at {:?}", rootfs_path);
+		nix::mount::mount::<str, PathBuf, str, str>(
+			Some("overlay"),
+			&rootfs_path,
+			Some("overlay"),
+			nix::mount::MsFlags::empty(),
+			Some(overlay_data.as_str()),
+		)
+		.unwrap_or_else(|err| panic!("Could not create overlay-fs at {:?}: {}", rootfs_path, err));
+	}
 
 	//Setup mounts and devices
 	let setup_dev = if let Some(mounts) = args.config.spec.mounts() {
@@ -345,29 +434,39 @@ fn init_stage_child(args: SetupArgs) -> ! {
 		true
 	};
 
+	let in_user_namespace = linux_spec
+		.namespaces()
+		.as_ref()
+		.map(|namespaces| {
+			namespaces
+				.iter()
+				.any(|ns| ns.typ() == runtime::LinuxNamespaceType::User)
+		})
+		.unwrap_or(false);
+
 	if setup_dev {
-		devices::create_devices(linux_spec.devices(), &rootfs_path);
+		devices::create_devices(linux_spec.devices(), &rootfs_path, in_user_namespace);
 		devices::setup_ptmx(&rootfs_path);
 		devices::setup_dev_symlinks(&rootfs_path);
 	}
 
 	if args.config.is_hermit_container {
 		devices::mount_hermit_devices(&rootfs_path);
+		mounts::mount_vmm_socket_dir(&rootfs_path, Path::new(vmm_socket_dir.as_ref().unwrap()));
 		devices::create_tun(
 			&rootfs_path,
 			Uid::from_raw(args.config.spec.process().as_ref().unwrap().user().uid()),
 			Gid::from_raw(args.config.spec.process().as_ref().unwrap().user().gid()),
+			in_user_namespace,
 		);
 	}
 
 	//Run pre-start hooks
 	debug!("Signalling parent to run pre-start hooks");
-	let mut init_pipe = unsafe { File::from_raw_fd(args.init_pipe) };
 	init_pipe
 		.write_all(&[crate::consts::INIT_REQ_PRESTART_HOOKS])
 		.expect("Unable to write to init-pipe!");
 
-	let mut sig_buffer = [0u8];
 	init_pipe
 		.read_exact(&mut sig_buffer)
 		.expect("Could not read from init pipe!");
@@ -379,6 +478,17 @@ fn init_stage_child(args: SetupArgs) -> ! {
 		);
 	}
 
+	let mut size_buffer = [0u8; std::mem::size_of::<usize>()];
+	init_pipe
+		.read_exact(&mut size_buffer)
+		.expect("Could not read message size from init-pipe!");
+	let mut state_buffer = vec![0; usize::from_le_bytes(size_buffer)];
+	init_pipe
+		.read_exact(&mut state_buffer)
+		.expect("Could not read container state from init pipe!");
+	let mut container_state: State =
+		serde_json::from_slice(&state_buffer).expect("Could not parse container state!");
+
 	nix::unistd::chdir(&rootfs_path).unwrap_or_else(|_| {
 		panic!(
 			"Could not change directory to rootfs path {:?}",
@@ -386,7 +496,15 @@ fn init_stage_child(args: SetupArgs) -> ! {
 		)
 	});
 
-	//TODO: Run create hooks
+	// createContainer hooks run in the container namespaces, but before pivot_root
+	if let Some(hooks) = args.config.spec.hooks() {
+		hooks::run_hooks(
+			hooks.create_container(),
+			&container_state,
+			"createContainer",
+		)
+		.unwrap_or_else(|err| panic!("{}", err));
+	}
 
 	if args.config.cloneflags.contains(CloneFlags::CLONE_NEWNS) {
 		rootfs::pivot_root(&rootfs_path);
@@ -395,29 +513,44 @@ fn init_stage_child(args: SetupArgs) -> ! {
 		nix::unistd::chdir("/").expect("Could not chdir to / after chroot!");
 	}
 
-	let user_port: u16 = env::var("RUNH_USER_PORT")
-		.unwrap_or_else(|_| "0".to_string())
-		.parse()
-		.expect("RUNH_USER_PORT was not an unsigned integer!");
+	// The VM config was already validated by runh create
+	let vm_config = if args.config.is_hermit_container {
+		Some(
+			VmConfig::from_spec(&args.config.spec)
+				.unwrap_or_else(|err| panic!("Invalid VM configuration: {}", err)),
+		)
+	} else {
+		None
+	};
+	let mut vmm = vm_config
+		.as_ref()
+		.map(|vm_config| hermit::get_vmm(vm_config.vmm));
 
-	let hermit_network_config = if args.config.is_hermit_container && user_port == 0 {
-		match tokio_runtime.block_on(network::create_tap()) {
+	let hermit_network_config = match vm_config.as_ref().map(|vm_config| vm_config.network) {
+		Some(NetworkMode::Tap) => match tokio_runtime.block_on(network::create_tap()) {
 			Ok(config) => NetworkConfig::TapNetwork(config),
 			Err(err) => {
 				warn!("Hermit network setup could not be completed: {err}");
 				NetworkConfig::None
 			}
-		}
-	} else if args.config.is_hermit_container && user_port > 0 {
-		NetworkConfig::UserNetwork(user_port)
-	} else {
-		NetworkConfig::None
+		},
+		Some(NetworkMode::User(user_port)) => NetworkConfig::UserNetwork(user_port),
+		Some(NetworkMode::None) | None => NetworkConfig::None,
 	};
+	// Shut down the worker threads. Otherwise, they would receive the signals that
+	// the VMM supervisor forwards to the hermit VM.
+	drop(tokio_runtime);
 
 	//TODO: re-open /dev/null in the container if any std-fd points to it
 
 	let cwd = args.config.spec.process().as_ref().unwrap().cwd();
 	if !cwd.as_os_str().is_empty() {
+		if !cwd.is_absolute() {
+			panic!(
+				"The cwd {:?} of the container process is not absolute!",
+				cwd
+			);
+		}
 		mounts::create_all_dirs(&PathBuf::from(cwd));
 	}
 
@@ -448,7 +581,7 @@ fn init_stage_child(args: SetupArgs) -> ! {
 				ws_ypixel: 0,
 			});
 
-		console::setup_console(console_socket, win_size.as_ref());
+		console::setup_console(console_socket, win_size.as_ref(), true);
 	}
 
 	//Finalize rootfs
@@ -468,29 +601,52 @@ fn init_stage_child(args: SetupArgs) -> ! {
 		nix::unistd::sethostname(hostname).expect("Could not set hostname!");
 	}
 
-	//TODO: Apply apparmor profile
-	//TODO: Write sysctl keys
+	if let Some(profile) = args
+		.config
+		.spec
+		.process()
+		.as_ref()
+		.and_then(|process| process.apparmor_profile().as_ref())
+	{
+		apparmor::apply_profile(profile);
+	}
+
 	if let Some(sysctl) = args.config.spec.linux().as_ref().unwrap().sysctl().as_ref() {
 		for (key, value) in sysctl {
 			let key_path = key.replace('.', "/");
 			let full_path = PathBuf::from("/proc/sys").join(key_path);
-			let mut sysctl_file = OpenOptions::new()
+			let result = OpenOptions::new()
 				.mode(0o644)
 				.create(true)
 				.truncate(true)
 				.write(true)
 				.open(&full_path)
-				.unwrap_or_else(|_| panic!("Could not create sysctl entry at {:?}", full_path));
-			sysctl_file.write_all(value.as_bytes()).unwrap_or_else(|_| {
-				panic!(
-					"Could not write value {} to sysctl entry at {:?}",
-					value, full_path
-				)
-			});
+				.and_then(|mut sysctl_file| sysctl_file.write_all(value.as_bytes()));
+			match result {
+				Ok(_) => {}
+				// Most sysctls are not namespaced to the user namespace of rootless containers
+				Err(err) if rootless => warn!(
+					"Could not write value {} to sysctl entry at {:?} in rootless mode, skipping: {}",
+					value, full_path, err
+				),
+				Err(err) => panic!(
+					"Could not write value {} to sysctl entry at {:?}: {}",
+					value, full_path, err
+				),
+			}
 		}
 	}
 
-	//TODO: Manage readonly and mask paths
+	if let Some(linux) = args.config.spec.linux() {
+		for path in linux.readonly_paths().iter().flatten() {
+			debug!("Set {} read-only", path);
+			rootfs::set_path_read_only(Path::new(path));
+		}
+		for path in linux.masked_paths().iter().flatten() {
+			debug!("Mask {}", path);
+			rootfs::mask_path(Path::new(path));
+		}
+	}
 
 	// Set no_new_privileges
 	if let Some(process) = &args.config.spec.process() {
@@ -500,55 +656,76 @@ fn init_stage_child(args: SetupArgs) -> ! {
 		}
 	}
 
-	//TODO: Apply seccomp
 	//TODO: Finalize Namespace
 	// - Ensure all fd's are CLOEXEC
-	// - Change to cwd
-	// - Change user
-	// - Apply capabilities
+
+	// Try to enter the cwd before switching the user, as the container user might not be
+	// allowed to access it. Only permission errors are retried after the user switch.
+	let cwd = args.config.spec.process().as_ref().unwrap().cwd();
+	let mut chdir_as_user = false;
+	if !cwd.as_os_str().is_empty() {
+		match nix::unistd::chdir(cwd) {
+			Ok(_) => {}
+			Err(Errno::EACCES) | Err(Errno::EPERM) => chdir_as_user = true,
+			Err(err) => panic!("Could not change directory to cwd {:?}: {}", cwd, err),
+		}
+	}
+
+	// The VMM of hermit containers is switched to the container user when it is spawned,
+	// as runh init has to prepare its devices and sockets with root privileges.
+	let process = args.config.spec.process().as_ref().unwrap();
+
+	// Without no_new_privs, installing a seccomp filter requires CAP_SYS_ADMIN, which
+	// might be dropped by the user switch. Otherwise, the filter is loaded right before exec,
+	// so that the remaining setup of runh init is not restricted by it.
+	let no_new_privs = process.no_new_privileges().unwrap_or(false);
+	let seccomp = args
+		.config
+		.spec
+		.linux()
+		.as_ref()
+		.and_then(|linux| linux.seccomp().clone());
+	if !args.config.is_hermit_container {
+		if let Some(seccomp) = seccomp.as_ref().filter(|_| !no_new_privs) {
+			seccomp::apply_seccomp(seccomp, seccomp_listener.take(), &container_state);
+		}
+		switch_to_process_user(process, chdir_as_user);
+	}
 
 	//Verify the args[0] executable exists
-	let mut tap_fd = None;
+	let mut vmm_fds = hermit::VmmFds::default();
+	let mut preserved_fds = Vec::new();
+	let mut virtiofsd_cmd = None;
 
 	let exec_args = if args.config.is_hermit_container {
-		let app = args
-			.config
-			.spec
-			.process()
-			.as_ref()
-			.unwrap()
-			.args()
-			.as_ref()
-			.unwrap()
-			.first()
-			.expect("Container spec does not contain any args!")
-			.as_str();
-		let app_root = PathBuf::from(app)
-			.parent()
-			.expect("App path does not have a parent!")
-			.to_owned();
-		let kernel_path = app_root.join("hermit-loader");
-		let kernel = kernel_path.as_os_str().to_str().unwrap();
-		let micro_vm: u32 = env::var("RUNH_MICRO_VM")
-			.unwrap_or_else(|_| "0".to_string())
-			.parse()
-			.expect("RUNH_MICRO_VM was not an unsigned integer!");
-
-		tap_fd = if let NetworkConfig::TapNetwork(ref netconf) = hermit_network_config {
+		// The hermit environment is an overlay of the bundle rootfs, so the application
+		// is found like in runh create
+		let app_path = image::resolve_executable(&args.config.spec, Path::new("/"))
+			.unwrap_or_else(|err| panic!("Could not locate hermit application: {}", err));
+		let app = app_path.to_str().unwrap();
+		let vm_config = vm_config.as_ref().unwrap();
+
+		if let NetworkConfig::TapNetwork(ref netconf) = hermit_network_config {
 			let tap_file = OpenOptions::new()
 				.read(true)
 				.write(true)
 				.open(format!("/dev/tap{}", netconf.macvtap_index))
 				.expect("Could not open tap device file!");
-			Some(OwnedFd::from(tap_file))
-		} else {
-			None
-		};
+			vmm_fds.tap = Some(tap_file.as_raw_fd());
+			preserved_fds.push(OwnedFd::from(tap_file));
+		}
+		if let Some(socket_name) = vmm.as_ref().unwrap().control_socket() {
+			let socket_path = Path::new(crate::consts::VMM_SOCKET_DIR).join(socket_name);
+			let socket = bind_vmm_socket(&socket_path, process.user());
+			vmm_fds.control = Some(socket.as_raw_fd());
+			preserved_fds.push(socket);
+		}
+		if vmm.as_ref().unwrap().uses_virtiofsd(vm_config) {
+			virtiofsd_cmd = Some(get_virtiofsd_cmd(process.user()));
+		}
 
-		hermit::get_qemu_args(
-			kernel,
+		vmm.as_mut().unwrap().get_args(
 			app,
-			&hermit_network_config,
 			args.config
 				.spec
 				.process()
@@ -557,9 +734,12 @@ fn init_stage_child(args: SetupArgs) -> ! {
 				.args()
 				.as_ref()
 				.unwrap(),
-			micro_vm > 0,
-			std::fs::metadata("/dev/kvm").is_ok(),
-			&tap_fd.as_ref().map(AsRawFd::as_raw_fd),
+			&hermit_network_config,
+			vm_config,
+			vm_config
+				.kvm
+				.unwrap_or_else(|| std::fs::metadata("/dev/kvm").is_ok()),
+			&vmm_fds,
 		)
 	} else {
 		args.config
@@ -595,6 +775,7 @@ fn init_stage_child(args: SetupArgs) -> ! {
 	if let Some(log_pipe_fd) = log_pipe_fd {
 		debug!("Closing log pipe...");
 		nix::unistd::close(log_pipe_fd).expect("Could not close log pipe fd!");
+		log::set_max_level(log::LevelFilter::Off);
 	}
 
 	let mut exec_fifo = OpenOptions::new()
@@ -604,6 +785,16 @@ fn init_stage_child(args: SetupArgs) -> ! {
 		.open(format!("/proc/self/fd/{fifo_fd}"))
 		.expect("Could not open exec fifo!");
 
+	// Opening the fifo blocks until runh start is called, so startContainer hooks run now.
+	// As logging is no longer possible, failures are reported to runh start through the fifo.
+	if let Some(hooks) = args.config.spec.hooks() {
+		container_state.status = String::from("created");
+		if hooks::run_hooks(hooks.start_container(), &container_state, "startContainer").is_err() {
+			let _ = write!(exec_fifo, "\x01");
+			std::process::exit(1);
+		}
+	}
+
 	write!(exec_fifo, "\0").expect("Could not write to exec fifo!");
 	drop(exec_fifo);
 
@@ -611,49 +802,20 @@ fn init_stage_child(args: SetupArgs) -> ! {
 	nix::unistd::close(fifo_fd).expect("Could not close exec fifo O_PATH fd!");
 	nix::unistd::close(init_pipe.into_raw_fd()).expect("Could not close init pipe fd!");
 
+	// The devices and sockets of the hermit VM are prepared. Neither virtiofsd nor the VMM
+	// may gain capabilities beyond the bounding set of the container process.
 	if args.config.is_hermit_container {
-		let micro_vm: u32 = env::var("RUNH_MICRO_VM")
-			.unwrap_or_else(|_| "0".to_string())
-			.parse()
-			.expect("RUNH_MICRO_VM was not an unsigned integer!");
-
-		if micro_vm == 0 {
-			let virtiofsd_args: Vec<String> = [
-				"virtiofsd",
-				"--socket-path=/run/vhostqemu",
-				"--shared-dir",
-				"/root",
-				"--sandbox",
-				"none",
-				"--seccomp",
-				"none",
-				"--inode-file-handles=never",
-			]
-			.iter()
-			.map(|s| s.to_string())
-			.collect();
-
-			info!("Initialize virtiofsd: {}", virtiofsd_args.join(" "));
-
-			let virtiofsd_path_rel = PathBuf::from(
-				virtiofsd_args
-					.first()
-					.expect("Container spec does not contain any args!"),
-			);
-			let virtiofsd_path_abs = paths::find_in_path(virtiofsd_path_rel, None)
-				.expect("Could not determine location of args-executable!");
-
-			let mut cmd = std::process::Command::new(virtiofsd_path_abs);
-			cmd.arg0(virtiofsd_args.first().unwrap());
-			if virtiofsd_args.len() > 1 {
-				cmd.args(virtiofsd_args.get(1..).unwrap());
-			}
-			cmd.envs(std::env::vars());
-
-			let _child = cmd.spawn().expect("Unable to virtiofsd");
+		if let Some(capabilities) = process.capabilities() {
+			capabilities::apply_bounding_set(capabilities);
 		}
 	}
 
+	if let Some(mut virtiofsd_cmd) = virtiofsd_cmd {
+		// virtiofsd is reaped by the VMM supervisor below
+		#[allow(clippy::zombie_processes)]
+		let _child = virtiofsd_cmd.spawn().expect("Unable to virtiofsd");
+	}
+
 	let mut cmd = std::process::Command::new(exec_path_abs);
 	cmd.arg0(exec_args.first().unwrap());
 	if exec_args.len() > 1 {
@@ -661,11 +823,110 @@ fn init_stage_child(args: SetupArgs) -> ! {
 	}
 	cmd.envs(std::env::vars());
 
-	if let Some(tap_fd) = tap_fd {
-		cmd.preserved_fds(vec![tap_fd]);
+	// The exit status of the VMM has to be translated to the exit code of the hermit application
+	if let Some(vmm) = vmm {
+		cmd.preserved_fds(preserved_fds);
+		let vmm_process = process.clone();
+		let state = container_state;
+		// The seccomp filter only confines the VMM, not its supervisor and virtiofsd
+		unsafe {
+			cmd.pre_exec(move || {
+				std::panic::catch_unwind(AssertUnwindSafe(|| {
+					if let Some(seccomp) = seccomp.as_ref().filter(|_| !no_new_privs) {
+						seccomp::apply_seccomp(seccomp, seccomp_listener.take(), &state);
+					}
+					switch_to_process_user(&vmm_process, chdir_as_user);
+					if let Some(seccomp) = seccomp.as_ref().filter(|_| no_new_privs) {
+						seccomp::apply_seccomp(seccomp, seccomp_listener.take(), &state);
+					}
+				}))
+				.map_err(|_| std::io::Error::other("Could not switch to the container user"))
+			});
+		}
+		supervisor::supervise_vmm(
+			cmd,
+			vmm.as_ref(),
+			vm_config.as_ref().unwrap().shutdown_grace_period,
+			process.user(),
+		);
+	}
+
+	if let Some(seccomp) = seccomp.as_ref().filter(|_| no_new_privs) {
+		seccomp::apply_seccomp(seccomp, seccomp_listener, &container_state);
 	}
 	let error = cmd.exec();
 
 	//This point should not be reached on successful exec
 	panic!("exec failed with error {}", error)
 }
+
+/// Switch to the user of the container process and apply its capabilities.
+fn switch_to_process_user(process: &runtime::Process, chdir_as_user: bool) {
+	let capabilities = process.capabilities().as_ref();
+	if let Some(capabilities) = capabilities {
+		capabilities::apply_bounding_set(capabilities);
+		capabilities::set_keep_caps();
+	}
+	user::switch_user(process.user());
+	if chdir_as_user {
+		nix::unistd::chdir(process.cwd()).unwrap_or_else(|err| {
+			panic!(
+				"Could not change directory to cwd {:?} as container user: {}",
+				process.cwd(),
+				err
+			)
+		});
+	}
+	if let Some(capabilities) = capabilities {
+		capabilities::clear_keep_caps();
+		capabilities::apply_capabilities(capabilities);
+	}
+}
+
+/// Create a listening socket, which is passed to a process of the hermit VM. The socket
+/// belongs to the container user, so that the VMM can connect to it after the user switch.
+fn bind_vmm_socket(path: &Path, user: &runtime::User) -> OwnedFd {
+	if let Some(parent) = path.parent() {
+		std::fs::create_dir_all(parent)
+			.unwrap_or_else(|err| panic!("Could not create directory {:?}: {}", parent, err));
+	}
+	let _ = std::fs::remove_file(path);
+	let listener = UnixListener::bind(path)
+		.unwrap_or_else(|err| panic!("Could not create socket at {:?}: {}", path, err));
+	nix::unistd::chown(
+		path,
+		Some(Uid::from_raw(user.uid())),
+		Some(Gid::from_raw(user.gid())),
+	)
+	.unwrap_or_else(|err| panic!("Could not change owner of socket {:?}: {}", path, err));
+	OwnedFd::from(listener)
+}
+
+/// virtiofsd shares the container file system with the guest. It runs with the privileges
+/// of runh init and receives its socket, to which QEMU connects.
+fn get_virtiofsd_cmd(user: &runtime::User) -> std::process::Command {
+	let socket = bind_vmm_socket(Path::new(crate::consts::VIRTIOFSD_SOCKET_PATH), user);
+	let virtiofsd_args: Vec<String> = vec![
+		String::from("virtiofsd"),
+		format!("--fd={}", socket.as_raw_fd()),
+		String::from("--shared-dir"),
+		String::from("/root"),
+		String::from("--sandbox"),
+		String::from("none"),
+		String::from("--seccomp"),
+		String::from("none"),
+		String::from("--inode-file-handles=never"),
+	];
+
+	info!("Initialize virtiofsd: {}", virtiofsd_args.join(" "));
+
+	let virtiofsd_path_abs = paths::find_in_path(PathBuf::from(&virtiofsd_args[0]), None)
+		.expect("Could not determine location of virtiofsd!");
+
+	let mut cmd = std::process::Command::new(virtiofsd_path_abs);
+	cmd.arg0(&virtiofsd_args[0]);
+	cmd.args(&virtiofsd_args[1..]);
+	cmd.envs(std::env::vars());
+	cmd.preserved_fds(vec![socket]);
+	cmd
+}
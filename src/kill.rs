@@ -1,33 +1,112 @@
+use nix::sys::signal::Signal;
 use nix::unistd::Pid;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
 use std::{convert::TryFrom, path::PathBuf, str::FromStr};
 
-use crate::state;
+use crate::{cgroups, state};
 
-pub fn kill_container(project_dir: PathBuf, id: &str, sig: &str, all: bool) {
-	let container_state = state::get_container_state(project_dir, id)
-		.unwrap_or_else(|| panic!("Could not query state for container {}", id));
-	if container_state.status != "created" && container_state.status != "running" {
-		panic!("Cannot send signals to non-running containers!")
-	}
-
-	if all {
-		unimplemented!("Sending signals to all container processes is currently unimplemented!");
-	}
-
-	let pid = container_state.pid.unwrap();
-	let signal = if let Ok(sig_nr) = sig.parse::<i32>() {
-		nix::sys::signal::Signal::try_from(sig_nr)
-			.unwrap_or_else(|_| panic!("Could not parse signal number {}", sig))
+fn parse_signal(sig: &str) -> Signal {
+	if let Ok(sig_nr) = sig.parse::<i32>() {
+		Signal::try_from(sig_nr).unwrap_or_else(|_| panic!("Could not parse signal number {}", sig))
 	} else {
 		let signal_str = if !sig.starts_with("SIG") {
 			format!("SIG{sig}")
 		} else {
 			sig.to_owned()
 		};
-		nix::sys::signal::Signal::from_str(signal_str.as_str())
+		Signal::from_str(signal_str.as_str())
 			.unwrap_or_else(|_| panic!("Could not parse signal string {}", sig))
+	}
+}
+
+/// Find all processes in the PID namespace of the given init process by walking procfs.
+fn get_pid_namespace_members(init_pid: i32) -> Vec<i32> {
+	let pid_ns = |pid: i32| {
+		std::fs::metadata(format!("/proc/{pid}/ns/pid"))
+			.ok()
+			.map(|metadata| (metadata.dev(), metadata.ino()))
 	};
 
+	let Some(container_ns) = pid_ns(init_pid) else {
+		return Vec::new();
+	};
+	if pid_ns(std::process::id() as i32) == Some(container_ns) {
+		warn!("Container does not have its own PID namespace. Only the init process will be signalled!");
+		return vec![init_pid];
+	}
+
+	procfs::process::all_processes()
+		.expect("Could not read processes from procfs!")
+		.flatten()
+		.map(|process| process.pid())
+		.filter(|pid| pid_ns(*pid) == Some(container_ns))
+		.collect()
+}
+
+/// Send a signal to all container processes. The cgroup is frozen during this operation, so
+/// that processes cannot fork new children, which would escape the signal.
+pub fn signal_all_processes(container_dir: &Path, init_pid: Option<i32>, signal: Signal) {
+	let cgroup = cgroups::load_cgroup_path(container_dir).filter(|cgroup| cgroup.exists());
+
+	if let Some(cgroup) = cgroup {
+		if signal == Signal::SIGKILL && cgroups::kill_all(&cgroup) {
+			debug!("Killed all processes in cgroup {:?}", cgroup);
+			return;
+		}
+
+		let use_freezer = cgroups::has_freezer(&cgroup) && !cgroups::is_frozen(&cgroup);
+		if use_freezer {
+			cgroups::set_frozen(&cgroup, true);
+		}
+		for pid in cgroups::get_pids(&cgroup) {
+			debug!("Sending signal {} to container process {}", signal, pid);
+			if let Err(err) = nix::sys::signal::kill(Pid::from_raw(pid), signal) {
+				warn!(
+					"Could not send signal {} to process {}: {}",
+					signal, pid, err
+				);
+			}
+		}
+		if use_freezer {
+			cgroups::set_frozen(&cgroup, false);
+		}
+	} else if let Some(init_pid) = init_pid {
+		for pid in get_pid_namespace_members(init_pid) {
+			debug!("Sending signal {} to container process {}", signal, pid);
+			if let Err(err) = nix::sys::signal::kill(Pid::from_raw(pid), signal) {
+				warn!(
+					"Could not send signal {} to process {}: {}",
+					signal, pid, err
+				);
+			}
+		}
+	}
+}
+
+pub fn kill_container(project_dir: PathBuf, id: &str, sig: &str, all: bool) {
+	let container_state = state::get_container_state(project_dir.clone(), id)
+		.unwrap_or_else(|| panic!("Could not query state for container {}", id));
+	let signal = parse_signal(sig);
+
+	if all {
+		// Helper processes might outlive the init process, so stopped containers are allowed here
+		if container_state.status == "creating" {
+			panic!("Cannot send signals to containers that are still being created!");
+		}
+		signal_all_processes(&project_dir.join(id), container_state.pid, signal);
+		return;
+	}
+
+	if container_state.status != "created"
+		&& container_state.status != "running"
+		&& container_state.status != "paused"
+	{
+		panic!("Cannot send signals to non-running containers!")
+	}
+
+	// The VMM supervisor of hermit containers powers the VM down on SIGTERM and SIGINT
+	let pid = container_state.pid.unwrap();
 	nix::sys::signal::kill(Pid::from_raw(pid), signal).unwrap_or_else(|_| {
 		panic!(
 			"Could not send signal {} to container process ID  {}!",
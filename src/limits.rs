@@ -0,0 +1,57 @@
+use oci_spec::runtime::{PosixRlimit, PosixRlimitType};
+use std::fs::OpenOptions;
+use std::io::Write;
+
+fn get_resource(typ: PosixRlimitType) -> libc::__rlimit_resource_t {
+	match typ {
+		PosixRlimitType::RlimitCpu => libc::RLIMIT_CPU,
+		PosixRlimitType::RlimitFsize => libc::RLIMIT_FSIZE,
+		PosixRlimitType::RlimitData => libc::RLIMIT_DATA,
+		PosixRlimitType::RlimitStack => libc::RLIMIT_STACK,
+		PosixRlimitType::RlimitCore => libc::RLIMIT_CORE,
+		PosixRlimitType::RlimitRss => libc::RLIMIT_RSS,
+		PosixRlimitType::RlimitNproc => libc::RLIMIT_NPROC,
+		PosixRlimitType::RlimitNofile => libc::RLIMIT_NOFILE,
+		PosixRlimitType::RlimitMemlock => libc::RLIMIT_MEMLOCK,
+		PosixRlimitType::RlimitAs => libc::RLIMIT_AS,
+		PosixRlimitType::RlimitLocks => libc::RLIMIT_LOCKS,
+		PosixRlimitType::RlimitSigpending => libc::RLIMIT_SIGPENDING,
+		PosixRlimitType::RlimitMsgqueue => libc::RLIMIT_MSGQUEUE,
+		PosixRlimitType::RlimitNice => libc::RLIMIT_NICE,
+		PosixRlimitType::RlimitRtprio => libc::RLIMIT_RTPRIO,
+		PosixRlimitType::RlimitRttime => libc::RLIMIT_RTTIME,
+	}
+}
+
+/// Apply the rlimits of the spec to the calling process.
+/// Raising a hard limit requires CAP_SYS_RESOURCE, so this has to happen before switching the user.
+pub fn set_rlimits(rlimits: &[PosixRlimit]) {
+	for rlimit in rlimits {
+		debug!(
+			"Set rlimit {:?} to soft {}, hard {}",
+			rlimit.typ(),
+			rlimit.soft(),
+			rlimit.hard()
+		);
+		let limit = libc::rlimit {
+			rlim_cur: rlimit.soft(),
+			rlim_max: rlimit.hard(),
+		};
+		if unsafe { libc::setrlimit(get_resource(rlimit.typ()), &limit) } != 0 {
+			panic!(
+				"Could not set rlimit {:?}: {}",
+				rlimit.typ(),
+				std::io::Error::last_os_error()
+			);
+		}
+	}
+}
+
+/// Set the OOM score adjustment of the calling process, which is inherited by the container process.
+pub fn set_oom_score_adj(oom_score_adj: i32) -> std::io::Result<()> {
+	debug!("Set oom_score_adj to {}", oom_score_adj);
+	OpenOptions::new()
+		.write(true)
+		.open("/proc/self/oom_score_adj")
+		.and_then(|mut file| file.write_all(oom_score_adj.to_string().as_bytes()))
+}
@@ -7,41 +7,64 @@ mod container;
 mod create;
 mod delete;
 mod kill;
-//mod exec;
+
+mod apparmor;
+mod capabilities;
+mod cgroups;
+mod cloned_binary;
+mod cloud_hypervisor;
 mod console;
 mod consts;
 mod devices;
+mod environment;
+mod exec;
 mod flags;
 mod hermit;
+mod hooks;
+mod image;
 mod init;
+mod limits;
 mod list;
 mod logging;
 mod mounts;
 mod namespaces;
 mod network;
 mod paths;
+mod pause;
+mod qmp;
 mod rootfs;
+mod rootless;
+mod run;
+mod seccomp;
 mod spec;
 mod start;
 mod state;
+mod supervisor;
+mod syscalls;
+mod systemd;
+mod user;
+mod vm_config;
 
 use crate::create::*;
 use crate::delete::*;
-//use crate::exec::*;
+use crate::exec::*;
 use crate::init::*;
 use crate::kill::*;
 use crate::list::*;
 use crate::logging::*;
+use crate::pause::*;
+use crate::run::*;
 use crate::spec::*;
 use crate::start::*;
 use crate::state::*;
 use clap::{crate_version, Parser, Subcommand};
+use std::env;
 use std::fs::DirBuilder;
 use std::os::unix::fs::DirBuilderExt;
-use std::{env, path::PathBuf};
+use std::path::{Path, PathBuf};
 
 fn parse_matches(cli: &Cli) {
-	let project_dir = &cli.root;
+	let project_dir = &cli.root.clone().unwrap_or_else(rootless::get_default_root);
 
 	if !project_dir.exists() {
 		DirBuilder::new()
@@ -73,13 +96,23 @@ fn parse_matches(cli: &Cli) {
 		cli.debug_log,
 	);
 	info!("Welcome to runh {}", crate_version!());
+	if cli.root.is_none() && project_dir.as_path() == Path::new(rootless::LEGACY_ROOT) {
+		warn!(
+			"Using the legacy root directory {}. Pass --root /run/runh to use the new default.",
+			rootless::LEGACY_ROOT
+		);
+	}
 	debug!(
 		"Runh was started with command {}",
 		env::args().collect::<Vec<String>>().join(" ")
 	);
 
 	match &cli.command {
-		Commands::Spec { bundle, args } => create_spec(bundle.clone(), args.clone()),
+		Commands::Spec {
+			bundle,
+			args,
+			rootless,
+		} => create_spec(bundle.clone(), args.clone(), *rootless),
 		Commands::Create {
 			container_id,
 			bundle,
@@ -92,8 +125,11 @@ fn parse_matches(cli: &Cli) {
 			pid_file.clone(),
 			console_socket.clone(),
 			cli.hermit_env.clone(),
+			cli.hermit_env_source.clone(),
 			cli.debug_config,
 			cli.log_level,
+			false,
+			cli.systemd_cgroup,
 		),
 		Commands::Delete {
 			container_id,
@@ -104,8 +140,56 @@ fn parse_matches(cli: &Cli) {
 			signal,
 			all,
 		} => kill_container(project_dir.clone(), container_id, signal, *all),
+		Commands::Exec {
+			container_id,
+			process,
+			detach,
+			pid_file,
+			console_socket,
+			tty,
+			cwd,
+			env,
+			command,
+			command_options,
+		} => exec_container(
+			project_dir.clone(),
+			container_id,
+			process.clone(),
+			command.clone(),
+			command_options.clone(),
+			*tty,
+			cwd.clone(),
+			env.clone(),
+			*detach,
+			pid_file.clone(),
+			console_socket.clone(),
+		),
 		Commands::Start { container_id } => start_container(project_dir.clone(), container_id),
+		Commands::Run {
+			container_id,
+			bundle,
+			pid_file,
+			console_socket,
+			detach,
+		} => run_container(
+			project_dir.clone(),
+			container_id,
+			bundle.clone(),
+			pid_file.clone(),
+			console_socket.clone(),
+			*detach,
+			cli.hermit_env.clone(),
+			cli.hermit_env_source.clone(),
+			cli.debug_config,
+			cli.log_level,
+			cli.systemd_cgroup,
+		),
+		Commands::Pause { container_id } => pause_container(project_dir.clone(), container_id),
+		Commands::Resume { container_id } => resume_container(project_dir.clone(), container_id),
 		Commands::List => list_containers(project_dir.clone()),
+		Commands::InstallEnv => {
+			hermit::install_environment(project_dir, &cli.hermit_env, &cli.hermit_env_source)
+		}
 		Commands::Init => init_container(),
 		_ => {
 			error!(
@@ -128,6 +212,10 @@ enum Commands {
 		/// container arguments
 		#[arg(short = 'a', long)]
 		args: Vec<String>,
+
+		/// Generate a specification for unprivileged users, which maps the current user to root
+		#[arg(long, default_value_t)]
+		rootless: bool,
 	},
 	/// Query container state
 	State {
@@ -172,9 +260,32 @@ enum Commands {
 	Exec {
 		/// Id of the container
 		container_id: String,
+		/// Path to a process.json describing the process to execute
+		#[arg(short = 'p', long)]
+		process: Option<PathBuf>,
+		/// Detach from the executed process
+		#[arg(short = 'd', long, default_value_t)]
+		detach: bool,
+		/// File to write the process id to
+		#[arg(long)]
+		pid_file: Option<PathBuf>,
+		/// Path to an AF_UNIX socket for console IO
+		#[arg(long)]
+		console_socket: Option<PathBuf>,
+		/// Allocate a pseudo-TTY (ignored if --process is given)
+		#[arg(short = 't', long, default_value_t)]
+		tty: bool,
+		/// Current working directory inside the container (ignored if --process is given)
+		#[arg(long)]
+		cwd: Option<PathBuf>,
+		/// Additional environment variables in the form NAME=VALUE (ignored if --process is given)
+		#[arg(short = 'e', long)]
+		env: Vec<String>,
 		/// Command, which will be executed in the container
-		command: String,
+		#[arg(required_unless_present = "process")]
+		command: Option<String>,
 		/// Arguments of the command
+		#[arg(trailing_var_arg = true, allow_hyphen_values = true)]
 		command_options: Vec<String>,
 	},
 	/// Executes the user defined process in a created container
@@ -182,8 +293,37 @@ enum Commands {
 		/// Id of the container
 		container_id: String,
 	},
+	/// Create and start a container and attach to its process until it exits
+	Run {
+		/// Id of the container
+		container_id: String,
+		/// path to the root of the bundle directory
+		#[arg(short = 'b', long)]
+		bundle: PathBuf,
+		/// File to write the process id to
+		#[arg(long)]
+		pid_file: Option<PathBuf>,
+		/// Path to an AF_UNIX socket for console IO (only used with --detach)
+		#[arg(long)]
+		console_socket: Option<PathBuf>,
+		/// Do not attach to the container process after starting it
+		#[arg(short = 'd', long, default_value_t)]
+		detach: bool,
+	},
+	/// Suspend all processes of a running container
+	Pause {
+		/// Id of the container
+		container_id: String,
+	},
+	/// Resume all processes of a paused container
+	Resume {
+		/// Id of the container
+		container_id: String,
+	},
 	/// Lists containers started by runh with the given root
 	List,
+	/// Install the hermit-environment from --hermit-env-source before any container is created
+	InstallEnv,
 	/// Init process running inside a newly created container. Do not use outside of runh!
 	Init,
 	/// Checkpoint a running container (not supported)
@@ -197,9 +337,9 @@ enum Commands {
 #[command(next_line_help = true)]
 #[command(propagate_version = true)]
 struct Cli {
-	/// root directory for storage of vm state
-	#[arg(long, default_value = "/run/user/1000/runh", value_name = "ROOT")]
-	root: PathBuf,
+	/// root directory for storage of vm state. Defaults to /run/runh (or /run/user/1000/runh, if it exists) for root and $XDG_RUNTIME_DIR/runh for unprivileged users
+	#[arg(long, value_name = "ROOT")]
+	root: Option<PathBuf>,
 
 	/// The logging level of the application
 	#[arg(short = 'l', long, default_value_t, value_enum)]
@@ -217,6 +357,10 @@ struct Cli {
 	#[arg(long, value_name = "HERMIT_ENV_PATH")]
 	hermit_env: Option<PathBuf>,
 
+	/// Tarball or OCI image layout to install the hermit-environment from, if it does not exist yet
+	#[arg(long, value_name = "HERMIT_ENV_SOURCE")]
+	hermit_env_source: Option<PathBuf>,
+
 	/// Write out any logs to the runh root directory in addition to the specified log path.
 	#[arg(long, default_value_t)]
 	debug_log: bool,
@@ -225,7 +369,7 @@ struct Cli {
 	#[arg(long, default_value_t)]
 	debug_config: bool,
 
-	/// Currently unimplemented!
+	/// Use systemd for cgroup management. Expects cgroupsPath in the form slice:prefix:name
 	#[arg(long)]
 	systemd_cgroup: bool,
 
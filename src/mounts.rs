@@ -10,7 +10,7 @@ use std::{
 	path::PathBuf,
 };
 
-use crate::rootfs;
+use crate::{cgroups, consts, rootfs};
 
 #[derive(Clone)]
 pub struct MountOptions {
@@ -52,6 +52,25 @@ pub fn mount_console(slave_path: &Path) {
 	let _ = nix::sys::stat::umask(old_umask);
 }
 
+/// Bind-mount the directory of the VMM sockets of a hermit container from its state
+/// directory, so that runh can reach the sockets from the host.
+pub fn mount_vmm_socket_dir(rootfs: &Path, socket_dir: &Path) {
+	let destination_rel = Path::new(consts::VMM_SOCKET_DIR);
+	let destination = rootfs::resolve_in_rootfs(destination_rel, rootfs);
+	create_all_dirs(&destination);
+	mount_with_flags(
+		"bind",
+		socket_dir,
+		destination_rel,
+		&destination,
+		MountOptions {
+			mount_flags: MsFlags::MS_BIND,
+			..Default::default()
+		},
+		None,
+	);
+}
+
 pub fn configure_mounts(
 	mounts: &[runtime::Mount],
 	rootfs: &Path,
@@ -237,18 +256,20 @@ pub fn configure_mounts(
 						}
 					}
 					Some("cgroup") => {
-						//TODO: Additional checks for cGroup v1 vs v2,
-						//		mount might fail when the cgroup-NS was not unshared earlier
-						// create_all_dirs(&destination_resolved);
-						// mount_with_flags(
-						// 	"cgroup2",
-						// 	&mount_src,
-						// 	&mount_dest,
-						// 	&destination_resolved,
-						// 	mount_options.clone(),
-						// 	mount_label.as_ref(),
-						// );
-						warn!("Warning: cgroups are currently unimplemented!");
+						if cgroups::is_cgroup_v2() {
+							// Inside a cgroup namespace, the container cgroup becomes the root of the mounted hierarchy
+							create_all_dirs(&destination_resolved);
+							mount_with_flags(
+								"cgroup2",
+								&PathBuf::from("cgroup2"),
+								&mount_dest,
+								&destination_resolved,
+								mount_options.clone(),
+								mount_label.as_ref(),
+							);
+						} else {
+							warn!("Warning: cgroup v1 mounts are currently unimplemented!");
+						}
 					}
 					_ => {
 						if destination_resolved.starts_with(rootfs.join("proc")) {
@@ -358,7 +379,7 @@ fn open_trough_procfd(
 	full_dest: &Path,
 	options: &mut MountOptions,
 ) -> File {
-	if mount_dest.to_path_buf() == PathBuf::from("/dev") || device == "tmpfs" {
+	if mount_dest == Path::new("/dev") || device == "tmpfs" {
 		options.mount_flags.remove(MsFlags::MS_RDONLY);
 	}
 
@@ -1,6 +1,10 @@
-use crate::flags;
+use crate::{flags, paths};
+use nix::sched::CloneFlags;
+use nix::unistd::{Gid, Uid};
 use oci_spec::runtime;
-use std::fs::File;
+use std::fs::{self, File};
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
 
 struct ConfiguredNamespace<'a>(File, &'a runtime::LinuxNamespace);
 
@@ -26,6 +30,9 @@ pub fn join_namespaces(namespaces: &[runtime::LinuxNamespace]) {
 		}
 	}
 
+	// The user namespace has to be joined first, as it owns all other namespaces
+	configured_ns.sort_by_key(|ns_config| ns_config.1.typ() != runtime::LinuxNamespaceType::User);
+
 	for ns_config in &configured_ns {
 		debug!("joining namespace {:?}", ns_config.1);
 		let flags = flags::get_cloneflag(ns_config.1.typ());
@@ -33,3 +40,171 @@ pub fn join_namespaces(namespaces: &[runtime::LinuxNamespace]) {
 			.unwrap_or_else(|_| panic!("Failed to join NS {:?}", ns_config.1));
 	}
 }
+
+/// Namespaces of a running process in the order in which they have to be joined.
+const PROCESS_NAMESPACES: [(&str, runtime::LinuxNamespaceType); 8] = [
+	("user", runtime::LinuxNamespaceType::User),
+	("ipc", runtime::LinuxNamespaceType::Ipc),
+	("uts", runtime::LinuxNamespaceType::Uts),
+	("net", runtime::LinuxNamespaceType::Network),
+	("pid", runtime::LinuxNamespaceType::Pid),
+	("cgroup", runtime::LinuxNamespaceType::Cgroup),
+	("time", runtime::LinuxNamespaceType::Time),
+	("mnt", runtime::LinuxNamespaceType::Mount),
+];
+
+/// Join all namespaces of the process with the given PID that differ from our own.
+/// The PID namespace only applies to children forked after this call.
+pub fn join_process_namespaces(pid: i32) {
+	let mut configured_ns: Vec<(File, CloneFlags)> = Vec::new();
+	for (name, typ) in PROCESS_NAMESPACES {
+		let ns_path = PathBuf::from(format!("/proc/{pid}/ns/{name}"));
+		let own_path = PathBuf::from(format!("/proc/self/ns/{name}"));
+		let (Ok(ns_stat), Ok(own_stat)) = (ns_path.metadata(), own_path.metadata()) else {
+			debug!("Namespace {name} is not available, skipping in join_process_namespaces");
+			continue;
+		};
+		if ns_stat.dev() == own_stat.dev() && ns_stat.ino() == own_stat.ino() {
+			debug!("Process {pid} shares namespace {name} with runh, skipping");
+			continue;
+		}
+		configured_ns.push((
+			File::open(&ns_path)
+				.unwrap_or_else(|_| panic!("failed to open {:?} for NS {}", ns_path, name)),
+			flags::get_cloneflag(typ),
+		));
+	}
+
+	for (file, flags) in &configured_ns {
+		debug!("joining namespace {:?} of process {}", flags, pid);
+		nix::sched::setns(file, *flags)
+			.unwrap_or_else(|err| panic!("Failed to join NS {:?}: {}", flags, err));
+	}
+}
+
+/// Checks whether the spec requests a new user namespace.
+pub fn has_new_user_namespace(spec: &runtime::Spec) -> bool {
+	spec.linux()
+		.as_ref()
+		.and_then(|linux| linux.namespaces().as_ref())
+		.map(|namespaces| {
+			namespaces.iter().any(|ns| {
+				ns.typ() == runtime::LinuxNamespaceType::User
+					&& ns
+						.path()
+						.as_ref()
+						.is_none_or(|path| path.as_os_str().is_empty())
+			})
+		})
+		.unwrap_or(false)
+}
+
+/// Checks whether the current process may call setgroups. This is not the case in user
+/// namespaces whose gid_map was written by an unprivileged process.
+pub fn is_setgroups_allowed() -> bool {
+	fs::read_to_string("/proc/self/setgroups")
+		.map(|setgroups| setgroups.trim() != "deny")
+		.unwrap_or(true)
+}
+
+fn map_root_id(mappings: &Option<Vec<runtime::LinuxIdMapping>>) -> u32 {
+	mappings
+		.iter()
+		.flatten()
+		.find(|mapping| mapping.container_id() == 0)
+		.map(|mapping| mapping.host_id())
+		.expect("The user namespace does not map the root user!")
+}
+
+/// Determine the host uid and gid of the container root user.
+pub fn get_host_root_ids(spec: &runtime::Spec) -> (Uid, Gid) {
+	if !has_new_user_namespace(spec) {
+		return (Uid::from_raw(0), Gid::from_raw(0));
+	}
+
+	let linux = spec.linux().as_ref().unwrap();
+	(
+		Uid::from_raw(map_root_id(linux.uid_mappings())),
+		Gid::from_raw(map_root_id(linux.gid_mappings())),
+	)
+}
+
+fn format_id_mappings(mappings: &[runtime::LinuxIdMapping]) -> String {
+	mappings
+		.iter()
+		.map(|mapping| {
+			format!(
+				"{} {} {}\n",
+				mapping.container_id(),
+				mapping.host_id(),
+				mapping.size()
+			)
+		})
+		.collect()
+}
+
+/// Map the IDs with the setuid helpers of the shadow package, which allow
+/// unprivileged users to use the subordinate IDs from /etc/subuid and /etc/subgid.
+fn run_id_map_helper(helper: &str, pid: i32, mappings: &[runtime::LinuxIdMapping]) -> bool {
+	let Some(helper_path) = paths::find_in_path(PathBuf::from(helper), None) else {
+		return false;
+	};
+
+	let mut cmd = std::process::Command::new(helper_path);
+	cmd.arg(pid.to_string());
+	for mapping in mappings {
+		cmd.arg(mapping.container_id().to_string())
+			.arg(mapping.host_id().to_string())
+			.arg(mapping.size().to_string());
+	}
+	let output = cmd
+		.output()
+		.unwrap_or_else(|err| panic!("Could not run {}: {}", helper, err));
+	if !output.status.success() {
+		panic!(
+			"{} returned exit status {}. Stderr: {}",
+			helper,
+			output.status,
+			String::from_utf8_lossy(&output.stderr)
+		);
+	}
+	true
+}
+
+/// Write the uid and gid mappings of the user namespace of the process with the given PID.
+pub fn write_id_mappings(pid: i32, linux: &runtime::Linux) {
+	let uid_mappings = linux
+		.uid_mappings()
+		.as_ref()
+		.filter(|mappings| !mappings.is_empty())
+		.expect("A new user namespace requires linux.uidMappings!");
+	let gid_mappings = linux
+		.gid_mappings()
+		.as_ref()
+		.filter(|mappings| !mappings.is_empty())
+		.expect("A new user namespace requires linux.gidMappings!");
+	let proc_dir = PathBuf::from(format!("/proc/{pid}"));
+
+	if nix::unistd::geteuid().is_root() {
+		debug!("Writing id mappings of process {}", pid);
+		fs::write(proc_dir.join("gid_map"), format_id_mappings(gid_mappings))
+			.expect("Could not write gid_map!");
+		fs::write(proc_dir.join("uid_map"), format_id_mappings(uid_mappings))
+			.expect("Could not write uid_map!");
+		return;
+	}
+
+	// Each map is written by its helper if available. Without helpers, unprivileged
+	// users can only map their own IDs and must disable setgroups before writing gid_map.
+	if !run_id_map_helper("newgidmap", pid, gid_mappings) {
+		warn!("newgidmap not found, only the gid of the current user can be mapped!");
+		fs::write(proc_dir.join("setgroups"), "deny").expect("Could not write setgroups!");
+		fs::write(proc_dir.join("gid_map"), format_id_mappings(gid_mappings))
+			.expect("Could not write gid_map! Unprivileged users can only map their own gid.");
+	}
+	if !run_id_map_helper("newuidmap", pid, uid_mappings) {
+		warn!("newuidmap not found, only the uid of the current user can be mapped!");
+		fs::write(proc_dir.join("uid_map"), format_id_mappings(uid_mappings))
+			.expect("Could not write uid_map! Unprivileged users can only map their own uid.");
+	}
+}
@@ -1,7 +1,23 @@
 use crate::rootfs;
 use std::path::PathBuf;
 
-pub fn find_in_path(mut path_relative: PathBuf, rootfs: Option<&PathBuf>) -> Option<PathBuf> {
+pub fn find_in_path(path_relative: PathBuf, rootfs: Option<&PathBuf>) -> Option<PathBuf> {
+	let path = if path_relative.is_absolute() {
+		String::new()
+	} else {
+		std::env::var("PATH")
+			.expect("PATH environment variable not set and no absolute args-path given!")
+	};
+	find_in_search_path(path_relative, rootfs, &path)
+}
+
+/// Like find_in_path, but with the given search path instead of the PATH of runh,
+/// e.g. the PATH of the container process.
+pub fn find_in_search_path(
+	mut path_relative: PathBuf,
+	rootfs: Option<&PathBuf>,
+	path: &str,
+) -> Option<PathBuf> {
 	if path_relative.is_absolute() {
 		if let Some(rootfs_path) = rootfs {
 			path_relative = rootfs::resolve_in_rootfs(&path_relative, rootfs_path);
@@ -12,8 +28,6 @@ pub fn find_in_path(mut path_relative: PathBuf, rootfs: Option<&PathBuf>) -> Opt
 			None
 		}
 	} else {
-		let path = std::env::var("PATH")
-			.expect("PATH environment variable not set and no absolute args-path given!");
 		for folder in path.split(':') {
 			let try_abs_path = rootfs.map_or_else(
 				|| PathBuf::from(folder).join(&path_relative),
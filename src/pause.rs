@@ -0,0 +1,60 @@
+use crate::qmp::{self, QmpClient};
+use crate::{cgroups, state};
+use std::path::{Path, PathBuf};
+
+/// Stop or continue the vCPUs of a hermit container, so that the guest clock does not jump
+/// while the QEMU process is frozen. Other containers do not provide a QMP socket.
+fn set_vcpus_running(container_dir: &Path, running: bool) {
+	let socket_path = qmp::get_socket_path(container_dir);
+	if !socket_path.exists() {
+		return;
+	}
+
+	let command = if running { "cont" } else { "stop" };
+	if let Err(err) = QmpClient::connect(&socket_path).and_then(|mut qmp| qmp.execute(command)) {
+		warn!(
+			"Could not {} the vCPUs of the hermit container: {}",
+			command, err
+		);
+	}
+}
+
+fn get_freezer_cgroup(project_dir: &Path, id: &str) -> PathBuf {
+	let cgroup = cgroups::load_cgroup_path(&project_dir.join(id))
+		.filter(|cgroup| cgroup.exists())
+		.unwrap_or_else(|| panic!("Container {} does not have a cgroup!", id));
+	if !cgroups::has_freezer(&cgroup) {
+		panic!("The cgroup freezer is not available for container {}!", id);
+	}
+	cgroup
+}
+
+pub fn pause_container(project_dir: PathBuf, id: &str) {
+	let container_state = state::get_container_state(project_dir.clone(), id)
+		.unwrap_or_else(|| panic!("Could not query state for container {}", id));
+	if container_state.status != "created" && container_state.status != "running" {
+		panic!(
+			"Cannot pause container {} with status {}!",
+			id, container_state.status
+		);
+	}
+
+	let cgroup = get_freezer_cgroup(&project_dir, id);
+	set_vcpus_running(&project_dir.join(id), false);
+	cgroups::set_frozen(&cgroup, true);
+}
+
+pub fn resume_container(project_dir: PathBuf, id: &str) {
+	let container_state = state::get_container_state(project_dir.clone(), id)
+		.unwrap_or_else(|| panic!("Could not query state for container {}", id));
+	if container_state.status != "paused" {
+		panic!(
+			"Cannot resume container {} with status {}!",
+			id, container_state.status
+		);
+	}
+
+	let cgroup = get_freezer_cgroup(&project_dir, id);
+	cgroups::set_frozen(&cgroup, false);
+	set_vcpus_running(&project_dir.join(id), true);
+}
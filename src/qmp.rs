@@ -0,0 +1,63 @@
+use serde_json::{json, Value};
+use std::error::Error;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::consts;
+
+/// A minimal client for the QEMU Machine Protocol of a hermit container.
+pub struct QmpClient {
+	reader: BufReader<UnixStream>,
+	writer: UnixStream,
+}
+
+/// Location of the QMP socket of a hermit container, as seen from the host.
+pub fn get_socket_path(container_dir: &Path) -> PathBuf {
+	container_dir
+		.join(consts::VMM_SOCKET_STATE_DIR)
+		.join(consts::QMP_SOCKET_NAME)
+}
+
+impl QmpClient {
+	/// Connect to the QMP socket and leave the capabilities negotiation mode.
+	pub fn connect(socket_path: &Path) -> Result<Self, Box<dyn Error>> {
+		let stream = UnixStream::connect(socket_path)?;
+		stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+		let mut client = Self {
+			reader: BufReader::new(stream.try_clone()?),
+			writer: stream,
+		};
+
+		let greeting = client.read_message()?;
+		if greeting.get("QMP").is_none() {
+			return Err(format!("Unexpected QMP greeting {greeting}").into());
+		}
+		client.execute("qmp_capabilities")?;
+		Ok(client)
+	}
+
+	fn read_message(&mut self) -> Result<Value, Box<dyn Error>> {
+		let mut line = String::new();
+		if self.reader.read_line(&mut line)? == 0 {
+			return Err("QMP socket was closed".into());
+		}
+		Ok(serde_json::from_str(&line)?)
+	}
+
+	/// Execute a QMP command and wait for its result. Asynchronous events are skipped.
+	pub fn execute(&mut self, command: &str) -> Result<Value, Box<dyn Error>> {
+		debug!("Executing QMP command {}", command);
+		writeln!(self.writer, "{}", json!({ "execute": command }))?;
+
+		loop {
+			let message = self.read_message()?;
+			if let Some(result) = message.get("return") {
+				return Ok(result.clone());
+			} else if let Some(error) = message.get("error") {
+				return Err(format!("QMP command {command} failed: {error}").into());
+			}
+		}
+	}
+}
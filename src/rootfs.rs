@@ -5,6 +5,7 @@ use std::{
 	path::PathBuf,
 };
 
+use nix::errno::Errno;
 use nix::mount::{MntFlags, MsFlags};
 use oci_spec::runtime::Spec;
 use path_clean::PathClean;
@@ -29,7 +30,7 @@ pub fn resolve_in_rootfs(destination_rel: &Path, rootfs: &Path) -> PathBuf {
 			.join(&destination_resolved)
 			.join(subpath)
 			.clean();
-		if clean_subpath == PathBuf::from("/") {
+		if clean_subpath == Path::new("/") {
 			destination_resolved.clear();
 			continue;
 		}
@@ -150,6 +151,57 @@ pub fn set_rootfs_read_only() {
 	} //The first mount should not fail unless we are in a user namespace so technically the content of the if-block is unreachable.
 }
 
+/// Bind-mount a path onto itself and remount it read-only. Paths that do not exist are skipped.
+pub fn set_path_read_only(path: &Path) {
+	if let Err(err) = nix::mount::mount::<Path, Path, str, str>(
+		Some(path),
+		path,
+		None,
+		MsFlags::MS_BIND | MsFlags::MS_REC,
+		None,
+	) {
+		if err == Errno::ENOENT {
+			return;
+		}
+		panic!("Could not bind-mount readonly path {:?}: {}", path, err);
+	}
+
+	let flags = MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY;
+	if nix::mount::mount::<str, Path, str, str>(None, path, None, flags, None).is_err() {
+		// Locked mount flags (e.g. nosuid in a user namespace) have to be kept when remounting
+		let stat = nix::sys::statvfs::statvfs(path)
+			.unwrap_or_else(|_| panic!("Could not stat readonly path {:?}!", path));
+		let mount_flags_new = flags | MsFlags::from_bits_truncate(stat.flags().bits());
+		nix::mount::mount::<str, Path, str, str>(None, path, None, mount_flags_new, None)
+			.unwrap_or_else(|err| panic!("Could not remount {:?} read-only: {}", path, err));
+	}
+}
+
+/// Hide a path from the container by mounting /dev/null over files
+/// and an empty read-only tmpfs over directories. Paths that do not exist are skipped.
+pub fn mask_path(path: &Path) {
+	match nix::mount::mount::<str, Path, str, str>(
+		Some("/dev/null"),
+		path,
+		None,
+		MsFlags::MS_BIND,
+		None,
+	) {
+		Ok(_) | Err(Errno::ENOENT) => {}
+		Err(Errno::ENOTDIR) => {
+			nix::mount::mount::<str, Path, str, str>(
+				Some("tmpfs"),
+				path,
+				Some("tmpfs"),
+				MsFlags::MS_RDONLY,
+				None,
+			)
+			.unwrap_or_else(|err| panic!("Could not mask directory {:?}: {}", path, err));
+		}
+		Err(err) => panic!("Could not mask path {:?}: {}", path, err),
+	}
+}
+
 pub fn pivot_root(rootfs: &Path) {
 	let old_root = OpenOptions::new()
 		.read(true)
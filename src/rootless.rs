@@ -0,0 +1,28 @@
+use std::path::PathBuf;
+
+/// Checks whether runh runs without root privileges. In this case, containers need a
+/// user namespace and host resources like devices and cgroups might not be accessible.
+pub fn is_rootless() -> bool {
+	!nix::unistd::geteuid().is_root()
+}
+
+/// Default root directory of runh versions without rootless support, which was also used by root.
+pub const LEGACY_ROOT: &str = "/run/user/1000/runh";
+
+/// Determine the default root directory: `/run/runh` for root and
+/// `$XDG_RUNTIME_DIR/runh` for unprivileged users. Root keeps using the legacy
+/// root directory if it exists, so that existing containers remain accessible.
+pub fn get_default_root() -> PathBuf {
+	if !is_rootless() {
+		let legacy_root = PathBuf::from(LEGACY_ROOT);
+		if legacy_root.is_dir() {
+			return legacy_root;
+		}
+		return PathBuf::from("/run/runh");
+	}
+
+	std::env::var_os("XDG_RUNTIME_DIR")
+		.map(PathBuf::from)
+		.unwrap_or_else(|| PathBuf::from(format!("/run/user/{}", nix::unistd::geteuid())))
+		.join("runh")
+}
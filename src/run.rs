@@ -0,0 +1,235 @@
+use crate::create::create_container;
+use crate::delete::delete_container;
+use crate::logging::LogLevel;
+use crate::start::start_container;
+use crate::{console, state};
+use nix::sys::signal::{SigSet, Signal};
+use nix::sys::socket::{ControlMessageOwned, MsgFlags};
+use nix::sys::termios;
+use nix::sys::wait::WaitStatus;
+use nix::unistd::Pid;
+use oci_spec::runtime::Spec;
+use std::fs::File;
+use std::io::{IoSliceMut, Read, Write};
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+
+/// Signals that are relayed to the container process in attached mode.
+const FORWARDED_SIGNALS: [Signal; 7] = [
+	Signal::SIGHUP,
+	Signal::SIGINT,
+	Signal::SIGQUIT,
+	Signal::SIGTERM,
+	Signal::SIGUSR1,
+	Signal::SIGUSR2,
+	Signal::SIGWINCH,
+];
+
+fn receive_pty_master(listener: UnixListener) -> OwnedFd {
+	let (stream, _) = listener
+		.accept()
+		.expect("Could not accept connection on console socket!");
+
+	let mut buffer = [0u8; 64];
+	let mut iov = [IoSliceMut::new(&mut buffer)];
+	let mut cmsg_buffer = nix::cmsg_space!([std::os::fd::RawFd; 1]);
+	let msg = nix::sys::socket::recvmsg::<()>(
+		stream.as_raw_fd(),
+		&mut iov,
+		Some(&mut cmsg_buffer),
+		MsgFlags::empty(),
+	)
+	.expect("Could not receive message from console socket!");
+
+	for cmsg in msg
+		.cmsgs()
+		.expect("Could not read control messages from console socket!")
+	{
+		if let ControlMessageOwned::ScmRights(fds) = cmsg {
+			if let Some(fd) = fds.first() {
+				return unsafe { OwnedFd::from_raw_fd(*fd) };
+			}
+		}
+	}
+	panic!("Did not receive a pty master over the console socket!");
+}
+
+fn forward_io(mut from: File, mut to: File) {
+	let mut buffer = [0u8; 4096];
+	while let Ok(bytes_read) = from.read(&mut buffer) {
+		if bytes_read == 0 || to.write_all(&buffer[..bytes_read]).is_err() {
+			break;
+		}
+		let _ = to.flush();
+	}
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn run_container(
+	project_dir: PathBuf,
+	id: &str,
+	bundle: PathBuf,
+	pidfile: Option<PathBuf>,
+	console_socket: Option<PathBuf>,
+	detach: bool,
+	hermit_env: Option<PathBuf>,
+	hermit_env_source: Option<PathBuf>,
+	debug_config: bool,
+	child_log_level: LogLevel,
+	systemd_cgroup: bool,
+) {
+	let spec = Spec::load(bundle.join("config.json")).expect("Unable to load config file");
+	let terminal = spec
+		.process()
+		.as_ref()
+		.and_then(|process| process.terminal())
+		.unwrap_or(false);
+
+	if detach {
+		if terminal && console_socket.is_none() {
+			panic!("Running a detached container with a terminal requires a console socket!");
+		}
+		create_container(
+			project_dir.clone(),
+			id,
+			bundle,
+			pidfile,
+			console_socket,
+			hermit_env,
+			hermit_env_source,
+			debug_config,
+			child_log_level,
+			false,
+			systemd_cgroup,
+		);
+		start_container(project_dir, id);
+		return;
+	}
+
+	// In attached mode, runh acts as console socket receiver itself
+	let console_socket_path = project_dir.join(format!("{id}-console.sock"));
+	let pty_receiver = if terminal {
+		let _ = std::fs::remove_file(&console_socket_path);
+		let listener = UnixListener::bind(&console_socket_path).unwrap_or_else(|_| {
+			panic!(
+				"Could not bind console socket at {:?}",
+				&console_socket_path
+			)
+		});
+		Some(std::thread::spawn(move || receive_pty_master(listener)))
+	} else {
+		None
+	};
+
+	create_container(
+		project_dir.clone(),
+		id,
+		bundle,
+		pidfile,
+		pty_receiver.as_ref().map(|_| console_socket_path.clone()),
+		hermit_env,
+		hermit_env_source,
+		debug_config,
+		child_log_level,
+		true,
+		systemd_cgroup,
+	);
+
+	let pty_master = pty_receiver.map(|receiver| {
+		let master = receiver.join().expect("Console socket receiver did panic!");
+		let _ = std::fs::remove_file(&console_socket_path);
+		master
+	});
+
+	let pid = state::get_container_state(project_dir.clone(), id)
+		.and_then(|container_state| container_state.pid)
+		.unwrap_or_else(|| panic!("Could not determine PID of container {}", id));
+
+	// The signal mask is only changed now, so that the container process does not inherit it
+	let mut signals = SigSet::empty();
+	for signal in FORWARDED_SIGNALS {
+		signals.add(signal);
+	}
+	signals
+		.thread_block()
+		.expect("Could not block forwarded signals!");
+
+	let stdin = std::io::stdin();
+	let saved_termios = pty_master.as_ref().and_then(|_| {
+		let saved = termios::tcgetattr(&stdin).ok()?;
+		let mut raw = saved.clone();
+		termios::cfmakeraw(&mut raw);
+		termios::tcsetattr(&stdin, termios::SetArg::TCSANOW, &raw)
+			.expect("Could not set terminal to raw mode!");
+		Some(saved)
+	});
+
+	let pty_master_fd = pty_master.as_ref().map(AsRawFd::as_raw_fd);
+	if let Some(master_fd) = pty_master_fd {
+		console::copy_winsize(stdin.as_raw_fd(), master_fd);
+	}
+
+	std::thread::spawn(move || loop {
+		match signals.wait() {
+			Ok(Signal::SIGWINCH) => {
+				if let Some(master_fd) = pty_master_fd {
+					console::copy_winsize(libc::STDIN_FILENO, master_fd);
+				}
+			}
+			Ok(signal) => {
+				debug!("Forwarding signal {} to container process {}", signal, pid);
+				let _ = nix::sys::signal::kill(Pid::from_raw(pid), signal);
+			}
+			Err(err) => warn!("Could not wait for signals: {}", err),
+		}
+	});
+
+	let output_forwarder = pty_master.map(|master| {
+		let master = File::from(master);
+		let master_writer = master.try_clone().expect("Could not duplicate pty master!");
+		std::thread::spawn(move || {
+			forward_io(
+				File::from(
+					std::io::stdin()
+						.as_fd()
+						.try_clone_to_owned()
+						.expect("Could not duplicate stdin!"),
+				),
+				master_writer,
+			)
+		});
+		std::thread::spawn(move || {
+			forward_io(
+				master,
+				File::from(
+					std::io::stdout()
+						.as_fd()
+						.try_clone_to_owned()
+						.expect("Could not duplicate stdout!"),
+				),
+			)
+		})
+	});
+
+	start_container(project_dir.clone(), id);
+
+	let exit_code = match nix::sys::wait::waitpid(Pid::from_raw(pid), None)
+		.expect("Could not wait for container process!")
+	{
+		WaitStatus::Exited(_, code) => code,
+		WaitStatus::Signaled(_, signal, _) => 128 + signal as i32,
+		status => panic!("Unexpected wait status {:?} of container process!", status),
+	};
+	info!("Container process exited with status {}", exit_code);
+
+	if let Some(forwarder) = output_forwarder {
+		let _ = forwarder.join();
+	}
+	if let Some(saved) = saved_termios {
+		let _ = termios::tcsetattr(&stdin, termios::SetArg::TCSANOW, &saved);
+	}
+
+	delete_container(project_dir, id, false);
+	std::process::exit(exit_code);
+}
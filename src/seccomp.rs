@@ -0,0 +1,534 @@
+use crate::consts;
+use crate::state::State;
+use crate::syscalls;
+use nix::sys::socket::{ControlMessage, MsgFlags};
+use oci_spec::runtime::{
+	Arch, LinuxSeccomp, LinuxSeccompAction, LinuxSeccompFilterFlag, LinuxSeccompOperator,
+	LinuxSyscall,
+};
+use seccompiler::{BpfProgram, TargetArch};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io::IoSlice;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+
+/// seccompiler does not know SCMP_ACT_NOTIFY, so these rules are compiled with
+/// SECCOMP_RET_TRACE and this value, which is replaced by SECCOMP_RET_USER_NOTIF afterwards.
+const NOTIFY_PLACEHOLDER: u32 = 0x4e4f;
+const SECCOMP_RET_TRACE: u32 = 0x7ff00000;
+const SECCOMP_RET_USER_NOTIF: u32 = 0x7fc00000;
+const BPF_RET_K: u16 = 0x06;
+
+/// Syscall rules of one filter. Rules without conditions are stored as `None`.
+type FilterRules = BTreeMap<String, Option<Vec<Value>>>;
+
+fn get_target_arch() -> TargetArch {
+	TargetArch::try_from(std::env::consts::ARCH).unwrap_or_else(|_| {
+		panic!(
+			"Seccomp filters are not supported on {}!",
+			std::env::consts::ARCH
+		)
+	})
+}
+
+fn get_seccomp_arch(arch: Arch) -> Option<TargetArch> {
+	match arch {
+		Arch::ScmpArchNative => Some(get_target_arch()),
+		Arch::ScmpArchX86_64 => Some(TargetArch::x86_64),
+		Arch::ScmpArchAarch64 => Some(TargetArch::aarch64),
+		Arch::ScmpArchRiscv64 => Some(TargetArch::riscv64),
+		_ => None,
+	}
+}
+
+/// The filter is only compiled for the native architecture, so the spec has to include it.
+/// Syscalls of further architectures (e.g. x86 next to x86_64) are rejected by the
+/// architecture check of the filter instead of the default action.
+fn check_architectures(seccomp: &LinuxSeccomp, arch: TargetArch) {
+	let Some(architectures) = seccomp
+		.architectures()
+		.as_ref()
+		.filter(|architectures| !architectures.is_empty())
+	else {
+		return;
+	};
+
+	if !architectures
+		.iter()
+		.any(|architecture| get_seccomp_arch(*architecture) == Some(arch))
+	{
+		panic!(
+			"Seccomp filter for the architectures {:?} does not include the host architecture {:?}!",
+			architectures, arch
+		);
+	}
+	let foreign: Vec<&Arch> = architectures
+		.iter()
+		.filter(|architecture| get_seccomp_arch(**architecture) != Some(arch))
+		.collect();
+	if !foreign.is_empty() {
+		debug!(
+			"Seccomp filter is only compiled for the native architecture {:?}, ignoring {:?}",
+			arch, foreign
+		);
+	}
+}
+
+fn get_action(action: LinuxSeccompAction, errno_ret: Option<u32>) -> Value {
+	match action {
+		LinuxSeccompAction::ScmpActAllow => json!("allow"),
+		LinuxSeccompAction::ScmpActErrno => {
+			json!({ "errno": errno_ret.unwrap_or(libc::EPERM as u32) })
+		}
+		LinuxSeccompAction::ScmpActKill | LinuxSeccompAction::ScmpActKillThread => {
+			json!("kill_thread")
+		}
+		LinuxSeccompAction::ScmpActKillProcess => json!("kill_process"),
+		LinuxSeccompAction::ScmpActTrap => json!("trap"),
+		LinuxSeccompAction::ScmpActTrace => {
+			json!({ "trace": errno_ret.unwrap_or(libc::EPERM as u32) })
+		}
+		LinuxSeccompAction::ScmpActLog => json!("log"),
+		LinuxSeccompAction::ScmpActNotify => json!({ "trace": NOTIFY_PLACEHOLDER }),
+	}
+}
+
+fn get_conditions(syscall: &LinuxSyscall) -> Option<Vec<Value>> {
+	let args = syscall.args().as_ref().filter(|args| !args.is_empty())?;
+	Some(
+		args.iter()
+			.map(|arg| {
+				let (op, value) = match arg.op() {
+					LinuxSeccompOperator::ScmpCmpNe => (json!("ne"), arg.value()),
+					LinuxSeccompOperator::ScmpCmpLt => (json!("lt"), arg.value()),
+					LinuxSeccompOperator::ScmpCmpLe => (json!("le"), arg.value()),
+					LinuxSeccompOperator::ScmpCmpEq => (json!("eq"), arg.value()),
+					LinuxSeccompOperator::ScmpCmpGe => (json!("ge"), arg.value()),
+					LinuxSeccompOperator::ScmpCmpGt => (json!("gt"), arg.value()),
+					LinuxSeccompOperator::ScmpCmpMaskedEq => (
+						json!({ "masked_eq": arg.value() }),
+						arg.value_two().unwrap_or(0),
+					),
+				};
+				json!({ "index": arg.index(), "type": "qword", "op": op, "val": value })
+			})
+			.collect(),
+	)
+}
+
+fn add_rule(rules: &mut FilterRules, name: &str, conditions: &Option<Vec<Value>>) {
+	match (rules.get_mut(name), conditions) {
+		// An unconditional rule already matches every invocation
+		(Some(None), _) => {}
+		(Some(existing), None) => *existing = None,
+		(Some(Some(existing)), Some(conditions)) => {
+			existing.push(json!({ "syscall": name, "args": conditions }))
+		}
+		(None, None) => {
+			rules.insert(name.to_owned(), None);
+		}
+		(None, Some(conditions)) => {
+			rules.insert(
+				name.to_owned(),
+				Some(vec![json!({ "syscall": name, "args": conditions })]),
+			);
+		}
+	}
+}
+
+fn to_json_filter(rules: &FilterRules, mismatch_action: &Value, match_action: &Value) -> Value {
+	let filter: Vec<Value> = rules
+		.iter()
+		.flat_map(|(name, conditional_rules)| match conditional_rules {
+			None => vec![json!({ "syscall": name })],
+			Some(conditional_rules) => conditional_rules.clone(),
+		})
+		.collect();
+	json!({
+		"mismatch_action": mismatch_action,
+		"match_action": match_action,
+		"filter": filter
+	})
+}
+
+/// Compile the seccomp configuration of the spec into BPF programs.
+///
+/// Every seccomp-bpf program only returns a single action on a match, so one program is
+/// generated per action. The kernel evaluates all of them and applies the action with the
+/// highest precedence. An additional program applies the default action to all syscalls
+/// that are not matched by any rule. Returns the programs with a flag whether the program
+/// contains SCMP_ACT_NOTIFY rules.
+///
+/// Every installed program also filters the seccomp syscall installing the next one, so the
+/// programs are returned in installation order: the notify program, which allows everything
+/// it does not forward to the seccomp agent, comes first and the default program last.
+fn compile(seccomp: &LinuxSeccomp) -> Vec<(BpfProgram, bool)> {
+	let arch = get_target_arch();
+	check_architectures(seccomp, arch);
+
+	let default_action = get_action(seccomp.default_action(), seccomp.default_errno_ret());
+	let mut all_rules = FilterRules::new();
+	let mut action_rules: BTreeMap<String, (Value, FilterRules)> = BTreeMap::new();
+
+	for syscall in seccomp.syscalls().iter().flatten() {
+		let conditions = get_conditions(syscall);
+		let action = get_action(syscall.action(), syscall.errno_ret());
+		for name in syscall.names() {
+			if !syscalls::is_known_syscall(name, arch) {
+				debug!("Ignoring unknown syscall {} in seccomp filter", name);
+				continue;
+			}
+			add_rule(&mut all_rules, name, &conditions);
+			if syscall.action() != LinuxSeccompAction::ScmpActAllow {
+				let (_, rules) = action_rules
+					.entry(action.to_string())
+					.or_insert_with(|| (action.clone(), FilterRules::new()));
+				add_rule(rules, name, &conditions);
+			}
+		}
+	}
+
+	let mut filters = serde_json::Map::new();
+	if seccomp.default_action() != LinuxSeccompAction::ScmpActAllow {
+		filters.insert(
+			String::from("default"),
+			to_json_filter(&all_rules, &default_action, &json!("allow")),
+		);
+	}
+	for (key, (action, rules)) in &action_rules {
+		filters.insert(key.clone(), to_json_filter(rules, &json!("allow"), action));
+	}
+
+	let notify_key = get_action(LinuxSeccompAction::ScmpActNotify, None).to_string();
+	let mut programs =
+		seccompiler::compile_from_json(Value::Object(filters).to_string().as_bytes(), arch)
+			.unwrap_or_else(|err| panic!("Could not compile seccomp filter: {}", err))
+			.into_iter()
+			.map(|(key, mut program)| {
+				let is_notify = key == notify_key;
+				if is_notify {
+					for instruction in program.iter_mut() {
+						if instruction.code == BPF_RET_K
+							&& instruction.k == SECCOMP_RET_TRACE | NOTIFY_PLACEHOLDER
+						{
+							instruction.k = SECCOMP_RET_USER_NOTIF;
+						}
+					}
+				}
+				(key, program, is_notify)
+			})
+			.collect::<Vec<_>>();
+	programs.sort_by_key(|(key, _, is_notify)| (!is_notify, key == "default"));
+	programs
+		.into_iter()
+		.map(|(_, program, is_notify)| (program, is_notify))
+		.collect()
+}
+
+fn install_filter(program: &BpfProgram, flags: libc::c_ulong) -> libc::c_long {
+	let prog = libc::sock_fprog {
+		len: program.len() as libc::c_ushort,
+		filter: program.as_ptr() as *mut libc::sock_filter,
+	};
+	let ret = unsafe {
+		libc::syscall(
+			libc::SYS_seccomp,
+			libc::SECCOMP_SET_MODE_FILTER,
+			flags,
+			&prog as *const libc::sock_fprog,
+		)
+	};
+	if ret < 0 {
+		panic!(
+			"Could not install seccomp filter: {}",
+			std::io::Error::last_os_error()
+		);
+	}
+	ret
+}
+
+/// Checks whether the seccomp configuration requires a seccomp notify listener.
+pub fn uses_notify(seccomp: &LinuxSeccomp) -> bool {
+	seccomp.default_action() == LinuxSeccompAction::ScmpActNotify
+		|| seccomp
+			.syscalls()
+			.iter()
+			.flatten()
+			.any(|syscall| syscall.action() == LinuxSeccompAction::ScmpActNotify)
+}
+
+/// Load the seccomp filter for the calling process. Returns the seccomp notify fd,
+/// if the filter contains SCMP_ACT_NOTIFY rules.
+pub fn load_seccomp(seccomp: &LinuxSeccomp) -> Option<OwnedFd> {
+	if seccomp.default_action() == LinuxSeccompAction::ScmpActNotify {
+		panic!("SCMP_ACT_NOTIFY cannot be used as default seccomp action!");
+	}
+
+	let mut flags: libc::c_ulong = 0;
+	for flag in seccomp.flags().iter().flatten() {
+		flags |= match flag {
+			LinuxSeccompFilterFlag::SeccompFilterFlagLog => libc::SECCOMP_FILTER_FLAG_LOG,
+			LinuxSeccompFilterFlag::SeccompFilterFlagTsync => libc::SECCOMP_FILTER_FLAG_TSYNC,
+			LinuxSeccompFilterFlag::SeccompFilterFlagSpecAllow => {
+				libc::SECCOMP_FILTER_FLAG_SPEC_ALLOW
+			}
+		};
+	}
+
+	let mut notify_fd = None;
+	for (program, is_notify) in compile(seccomp) {
+		debug!(
+			"Installing seccomp filter with {} instructions",
+			program.len()
+		);
+		if is_notify {
+			// A listener cannot be combined with TSYNC, but is only needed for the calling thread
+			let ret = install_filter(
+				&program,
+				(flags & !libc::SECCOMP_FILTER_FLAG_TSYNC) | libc::SECCOMP_FILTER_FLAG_NEW_LISTENER,
+			);
+			notify_fd = Some(unsafe { OwnedFd::from_raw_fd(ret as RawFd) });
+		} else {
+			install_filter(&program, flags);
+		}
+	}
+	notify_fd
+}
+
+/// Connect to the seccomp agent listening at `linux.seccomp.listenerPath`.
+pub fn connect_listener(seccomp: &LinuxSeccomp) -> UnixStream {
+	let listener_path = seccomp
+		.listener_path()
+		.as_ref()
+		.expect("SCMP_ACT_NOTIFY requires a seccomp listenerPath!");
+	UnixStream::connect(listener_path).unwrap_or_else(|err| {
+		panic!(
+			"Could not connect to seccomp listener at {:?}: {}",
+			listener_path, err
+		)
+	})
+}
+
+/// Load the seccomp filter and pass its notify fd over the connection to the seccomp agent,
+/// which has been established before entering the container.
+pub fn apply_seccomp(seccomp: &LinuxSeccomp, listener: Option<UnixStream>, state: &State) {
+	debug!("Apply seccomp filter");
+	if let Some(notify_fd) = load_seccomp(seccomp) {
+		let listener = listener.expect("SCMP_ACT_NOTIFY requires a seccomp listener!");
+		send_to_stream(&listener, seccomp, state, &notify_fd);
+	}
+}
+
+/// Send the seccomp notify fd and the container process state over an established
+/// connection to the seccomp agent.
+pub fn send_to_stream(
+	stream: &UnixStream,
+	seccomp: &LinuxSeccomp,
+	state: &State,
+	notify_fd: &OwnedFd,
+) {
+	let process_state = json!({
+		"ociVersion": consts::OCI_STATE_VERSION,
+		"fds": ["seccompFd"],
+		"pid": state.pid,
+		"metadata": seccomp.listener_metadata().clone().unwrap_or_default(),
+		"state": state,
+	})
+	.to_string();
+	let fds = [notify_fd.as_raw_fd()];
+	nix::sys::socket::sendmsg::<()>(
+		stream.as_raw_fd(),
+		&[IoSlice::new(process_state.as_bytes())],
+		&[ControlMessage::ScmRights(&fds)],
+		MsgFlags::empty(),
+		None,
+	)
+	.unwrap_or_else(|err| panic!("Could not send seccomp notify fd to listener: {}", err));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use oci_spec::runtime::{
+		LinuxSeccompArg, LinuxSeccompArgBuilder, LinuxSeccompBuilder, LinuxSyscallBuilder,
+	};
+
+	fn syscall(names: &[&str], action: LinuxSeccompAction) -> LinuxSyscall {
+		LinuxSyscallBuilder::default()
+			.names(
+				names
+					.iter()
+					.map(|name| name.to_string())
+					.collect::<Vec<_>>(),
+			)
+			.action(action)
+			.build()
+			.unwrap()
+	}
+
+	fn arg(index: usize, value: u64, value_two: u64, op: LinuxSeccompOperator) -> LinuxSeccompArg {
+		LinuxSeccompArgBuilder::default()
+			.index(index)
+			.value(value)
+			.value_two(value_two)
+			.op(op)
+			.build()
+			.unwrap()
+	}
+
+	fn seccomp(default_action: LinuxSeccompAction, syscalls: Vec<LinuxSyscall>) -> LinuxSeccomp {
+		LinuxSeccompBuilder::default()
+			.default_action(default_action)
+			.syscalls(syscalls)
+			.build()
+			.unwrap()
+	}
+
+	#[test]
+	fn conditions_of_arg_comparisons() {
+		let mut rule = syscall(&["personality"], LinuxSeccompAction::ScmpActAllow);
+		assert_eq!(get_conditions(&rule), None);
+
+		rule.set_args(Some(vec![
+			arg(0, 8, 0, LinuxSeccompOperator::ScmpCmpEq),
+			arg(1, 2, 0, LinuxSeccompOperator::ScmpCmpNe),
+			arg(2, 0x10, 0x20, LinuxSeccompOperator::ScmpCmpMaskedEq),
+		]));
+		assert_eq!(
+			get_conditions(&rule),
+			Some(vec![
+				json!({ "index": 0, "type": "qword", "op": "eq", "val": 8 }),
+				json!({ "index": 1, "type": "qword", "op": "ne", "val": 2 }),
+				json!({ "index": 2, "type": "qword", "op": { "masked_eq": 0x10 }, "val": 0x20 }),
+			])
+		);
+	}
+
+	#[test]
+	fn actions() {
+		assert_eq!(
+			get_action(LinuxSeccompAction::ScmpActErrno, None),
+			json!({ "errno": libc::EPERM })
+		);
+		assert_eq!(
+			get_action(LinuxSeccompAction::ScmpActErrno, Some(38)),
+			json!({ "errno": 38 })
+		);
+		assert_eq!(
+			get_action(LinuxSeccompAction::ScmpActKill, None),
+			json!("kill_thread")
+		);
+	}
+
+	#[test]
+	fn unconditional_rules_take_precedence() {
+		let conditions = Some(vec![
+			json!({ "index": 0, "type": "qword", "op": "eq", "val": 1 }),
+		]);
+		let mut rules = FilterRules::new();
+		add_rule(&mut rules, "clone", &conditions);
+		add_rule(&mut rules, "clone", &conditions);
+		assert_eq!(rules["clone"].as_ref().unwrap().len(), 2);
+
+		add_rule(&mut rules, "clone", &None);
+		add_rule(&mut rules, "clone", &conditions);
+		assert_eq!(rules["clone"], None);
+	}
+
+	#[test]
+	fn compile_one_program_per_action() {
+		let mut errno = syscall(&["mount", "umount2"], LinuxSeccompAction::ScmpActErrno);
+		errno.set_args(Some(vec![arg(0, 0, 0, LinuxSeccompOperator::ScmpCmpGt)]));
+		let programs = compile(&seccomp(
+			LinuxSeccompAction::ScmpActErrno,
+			vec![
+				syscall(&["read", "write"], LinuxSeccompAction::ScmpActAllow),
+				errno,
+				syscall(&["not_a_syscall"], LinuxSeccompAction::ScmpActKill),
+			],
+		));
+
+		// The default program and the errno program. The kill rule only names an unknown syscall.
+		assert_eq!(programs.len(), 2);
+		assert!(programs.iter().all(|(_, is_notify)| !is_notify));
+	}
+
+	#[test]
+	fn compile_allow_by_default_without_rules() {
+		let programs = compile(&seccomp(LinuxSeccompAction::ScmpActAllow, Vec::new()));
+		assert!(programs.is_empty());
+	}
+
+	#[test]
+	fn compile_notify_rules() {
+		let programs = compile(&seccomp(
+			LinuxSeccompAction::ScmpActAllow,
+			vec![syscall(&["mkdir"], LinuxSeccompAction::ScmpActNotify)],
+		));
+		assert_eq!(programs.len(), 1);
+
+		let (program, is_notify) = &programs[0];
+		assert!(is_notify);
+		let returns: Vec<u32> = program
+			.iter()
+			.filter(|instruction| instruction.code == BPF_RET_K)
+			.map(|instruction| instruction.k)
+			.collect();
+		assert!(returns.contains(&SECCOMP_RET_USER_NOTIF));
+		assert!(!returns.contains(&(SECCOMP_RET_TRACE | NOTIFY_PLACEHOLDER)));
+	}
+
+	#[test]
+	fn compile_notify_program_first() {
+		let programs = compile(&seccomp(
+			LinuxSeccompAction::ScmpActErrno,
+			vec![
+				syscall(&["read", "write"], LinuxSeccompAction::ScmpActAllow),
+				syscall(&["mkdir"], LinuxSeccompAction::ScmpActNotify),
+				syscall(&["mount"], LinuxSeccompAction::ScmpActKill),
+			],
+		));
+		let notify: Vec<bool> = programs.iter().map(|(_, is_notify)| *is_notify).collect();
+		assert_eq!(notify, vec![true, false, false]);
+	}
+
+	#[test]
+	fn notify_detection() {
+		assert!(!uses_notify(&seccomp(
+			LinuxSeccompAction::ScmpActErrno,
+			vec![syscall(&["read"], LinuxSeccompAction::ScmpActAllow)],
+		)));
+		assert!(uses_notify(&seccomp(
+			LinuxSeccompAction::ScmpActErrno,
+			vec![syscall(&["read"], LinuxSeccompAction::ScmpActNotify)],
+		)));
+	}
+
+	#[test]
+	fn architectures_include_host() {
+		let arch = get_target_arch();
+		let mut config = seccomp(LinuxSeccompAction::ScmpActAllow, Vec::new());
+		check_architectures(&config, arch);
+
+		config.set_architectures(Some(vec![Arch::ScmpArchNative]));
+		check_architectures(&config, arch);
+
+		config.set_architectures(Some(vec![
+			Arch::ScmpArchX86_64,
+			Arch::ScmpArchX86,
+			Arch::ScmpArchAarch64,
+		]));
+		check_architectures(&config, TargetArch::x86_64);
+		check_architectures(&config, TargetArch::aarch64);
+	}
+
+	#[test]
+	#[should_panic(expected = "does not include the host architecture")]
+	fn architectures_without_host() {
+		let mut config = seccomp(LinuxSeccompAction::ScmpActAllow, Vec::new());
+		config.set_architectures(Some(vec![Arch::ScmpArchX86, Arch::ScmpArchPpc64]));
+		check_architectures(&config, TargetArch::x86_64);
+	}
+}
@@ -1,21 +1,26 @@
 use oci_spec::runtime;
 use std::path;
 
-pub fn create_spec(bundle: path::PathBuf, args: Vec<String>) {
+pub fn create_spec(bundle: path::PathBuf, args: Vec<String>, rootless: bool) {
 	let mut config_file = bundle;
 	config_file.push("config.json");
 	let mut root = runtime::Root::default();
 	root.set_readonly(false.into());
-	let spec: runtime::Spec = runtime::SpecBuilder::default()
-		.process(
-			runtime::ProcessBuilder::default()
-				.args(args)
-				.build()
-				.unwrap(),
+	let mut spec: runtime::Spec = if rootless {
+		runtime::Spec::rootless(
+			nix::unistd::geteuid().as_raw(),
+			nix::unistd::getegid().as_raw(),
 		)
-		.root(root)
-		.build()
-		.unwrap();
+	} else {
+		runtime::Spec::default()
+	};
+	spec.set_process(Some(
+		runtime::ProcessBuilder::default()
+			.args(args)
+			.build()
+			.unwrap(),
+	));
+	spec.set_root(Some(root));
 	spec.save(config_file.to_str().unwrap())
 		.expect("Unable to write new specification file");
 }
@@ -1,4 +1,5 @@
 use crate::container::OCIContainer;
+use crate::{hooks, state};
 use std::fs::{self, File};
 use std::io::Read;
 use std::path::PathBuf;
@@ -36,6 +37,17 @@ pub fn start_container(mut project_dir: PathBuf, id: &str) {
 				info!("Container started successfully! Deleting exec fifo!");
 				std::fs::remove_file(project_dir.join("exec.fifo"))
 					.expect("Could not delete exec fifo!");
+
+				if let Some(hooks) = container.spec().hooks() {
+					let state =
+						state::get_container_state(project_dir.parent().unwrap().to_path_buf(), id)
+							.expect("Could not query state for poststart hooks!");
+					if let Err(err) = hooks::run_hooks(hooks.poststart(), &state, "poststart") {
+						warn!("{}", err);
+					}
+				}
+			} else if buffer[0] == 1 {
+				panic!("startContainer hook failed, the container process did exit!");
 			} else {
 				panic!(
 					"Invalid value read from fifo. Read byte was {:x}",
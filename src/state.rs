@@ -1,4 +1,4 @@
-use crate::{consts, container::OCIContainer};
+use crate::{cgroups, consts, container::OCIContainer};
 use serde::*;
 use std::{collections::HashMap, fs::OpenOptions, io::BufReader, path::PathBuf};
 
@@ -70,7 +70,12 @@ pub fn get_container_state(project_dir: PathBuf, id: &str) -> Option<State> {
 					procfs::process::ProcState::Zombie => "stopped",
 					procfs::process::ProcState::Dead => "stopped",
 					_ => {
-						if exec_fifo.exists() {
+						if cgroups::load_cgroup_path(&container_dir)
+							.map(|cgroup| cgroups::is_frozen(&cgroup))
+							.unwrap_or(false)
+						{
+							"paused"
+						} else if exec_fifo.exists() {
 							"created"
 						} else {
 							"running"
@@ -0,0 +1,118 @@
+use crate::hermit::Vmm;
+use crate::{capabilities, user};
+use nix::errno::Errno;
+use nix::sys::signal::{SigSet, Signal};
+use nix::sys::time::TimeSpec;
+use nix::sys::wait::{WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+use oci_spec::runtime;
+use std::convert::TryFrom;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// Signals that are relayed from the supervisor to the VMM.
+const FORWARDED_SIGNALS: [Signal; 6] = [
+	Signal::SIGHUP,
+	Signal::SIGINT,
+	Signal::SIGQUIT,
+	Signal::SIGTERM,
+	Signal::SIGUSR1,
+	Signal::SIGUSR2,
+];
+
+/// Wait for one of the given signals. Returns None if the timeout expired.
+fn wait_for_signal(signals: &SigSet, timeout: Option<Duration>) -> Result<Option<Signal>, Errno> {
+	let Some(timeout) = timeout else {
+		return signals.wait().map(Some);
+	};
+	let timeout = TimeSpec::from_duration(timeout);
+	let signal =
+		unsafe { libc::sigtimedwait(signals.as_ref(), std::ptr::null_mut(), timeout.as_ref()) };
+	match Errno::result(signal) {
+		Ok(signal) => Signal::try_from(signal).map(Some),
+		Err(Errno::EAGAIN) => Ok(None),
+		Err(err) => Err(err),
+	}
+}
+
+/// Run the VMM as child of runh init and exit with the exit code of the hermit application.
+/// Signals are forwarded to the VMM and other children (e.g. virtiofsd) are reaped.
+/// On SIGTERM and SIGINT, the guest is asked to power down. If it is still running after
+/// the grace period, the signal is forwarded to the VMM.
+/// Once the VMM is spawned, the supervisor runs as the container user without capabilities.
+/// Logging is not possible anymore at this point, as the log pipe is already closed.
+pub fn supervise_vmm(
+	mut cmd: Command,
+	vmm: &dyn Vmm,
+	shutdown_grace_period: Duration,
+	user: &runtime::User,
+) -> ! {
+	let mut signals = SigSet::empty();
+	for signal in FORWARDED_SIGNALS {
+		signals.add(signal);
+	}
+	signals.add(Signal::SIGCHLD);
+	signals
+		.thread_block()
+		.expect("Could not block forwarded signals!");
+
+	unsafe {
+		cmd.pre_exec(move || {
+			// The VMM must not inherit the signal mask of the supervisor
+			signals.thread_unblock()?;
+			// Do not leave an orphaned VMM behind, if the supervisor is killed
+			capctl::prctl::set_pdeathsig(Some(libc::SIGKILL)).map_err(std::io::Error::from)
+		});
+	}
+	// The VMM is reaped in the loop below
+	#[allow(clippy::zombie_processes)]
+	let child = cmd
+		.spawn()
+		.unwrap_or_else(|err| panic!("Unable to spawn VMM: {}", err));
+	let vmm_pid = Pid::from_raw(child.id() as i32);
+
+	// The VMM and its control socket belong to the container user, so signalling and
+	// controlling it does not require any privileges
+	user::switch_user(user);
+	capabilities::drop_capabilities();
+
+	if let Err(err) = vmm.boot() {
+		let _ = nix::sys::signal::kill(vmm_pid, Signal::SIGKILL);
+		panic!("Unable to boot the VM: {}", err);
+	}
+
+	let mut shutdown_request: Option<(Signal, Instant)> = None;
+	loop {
+		let timeout = shutdown_request
+			.map(|(_, deadline)| deadline.saturating_duration_since(Instant::now()));
+		match wait_for_signal(&signals, timeout) {
+			Ok(Some(Signal::SIGCHLD)) => loop {
+				match nix::sys::wait::waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+					Ok(WaitStatus::Exited(pid, status)) if pid == vmm_pid => {
+						std::process::exit(vmm.get_exit_code(status))
+					}
+					Ok(WaitStatus::Signaled(pid, signal, _)) if pid == vmm_pid => {
+						std::process::exit(128 + signal as i32)
+					}
+					Ok(WaitStatus::StillAlive) | Err(_) => break,
+					Ok(_) => {}
+				}
+			},
+			Ok(Some(signal @ (Signal::SIGTERM | Signal::SIGINT)))
+				if shutdown_request.is_none() && vmm.power_down() =>
+			{
+				shutdown_request = Some((signal, Instant::now() + shutdown_grace_period));
+			}
+			Ok(Some(signal)) => {
+				let _ = nix::sys::signal::kill(vmm_pid, signal);
+			}
+			// The guest did not power down within its grace period
+			Ok(None) => {
+				let (signal, _) = shutdown_request.take().unwrap();
+				let _ = nix::sys::signal::kill(vmm_pid, signal);
+			}
+			Err(_) => {}
+		}
+	}
+}
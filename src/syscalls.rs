@@ -0,0 +1,224 @@
+use seccompiler::TargetArch;
+
+// Names of the syscalls known to seccompiler 0.5, taken from its syscall tables (Linux 6.12).
+// seccompiler rejects filters with unknown names, so rules for syscalls of other
+// architectures or newer kernels are filtered out with these sorted tables beforehand.
+
+#[rustfmt::skip]
+const X86_64: &[&str] = &[
+	"_sysctl", "accept", "accept4", "access", "acct", "add_key", "adjtimex", "afs_syscall",
+	"alarm", "arch_prctl", "bind", "bpf", "brk", "cachestat", "capget", "capset", "chdir", "chmod",
+	"chown", "chroot", "clock_adjtime", "clock_getres", "clock_gettime", "clock_nanosleep",
+	"clock_settime", "clone", "clone3", "close", "close_range", "connect", "copy_file_range",
+	"creat", "create_module", "delete_module", "dup", "dup2", "dup3", "epoll_create",
+	"epoll_create1", "epoll_ctl", "epoll_ctl_old", "epoll_pwait", "epoll_pwait2", "epoll_wait",
+	"epoll_wait_old", "eventfd", "eventfd2", "execve", "execveat", "exit", "exit_group",
+	"faccessat", "faccessat2", "fadvise64", "fallocate", "fanotify_init", "fanotify_mark",
+	"fchdir", "fchmod", "fchmodat", "fchmodat2", "fchown", "fchownat", "fcntl", "fdatasync",
+	"fgetxattr", "finit_module", "flistxattr", "flock", "fork", "fremovexattr", "fsconfig",
+	"fsetxattr", "fsmount", "fsopen", "fspick", "fstat", "fstatfs", "fsync", "ftruncate", "futex",
+	"futex_requeue", "futex_wait", "futex_waitv", "futex_wake", "futimesat", "get_kernel_syms",
+	"get_mempolicy", "get_robust_list", "get_thread_area", "getcpu", "getcwd", "getdents",
+	"getdents64", "getegid", "geteuid", "getgid", "getgroups", "getitimer", "getpeername",
+	"getpgid", "getpgrp", "getpid", "getpmsg", "getppid", "getpriority", "getrandom", "getresgid",
+	"getresuid", "getrlimit", "getrusage", "getsid", "getsockname", "getsockopt", "gettid",
+	"gettimeofday", "getuid", "getxattr", "init_module", "inotify_add_watch", "inotify_init",
+	"inotify_init1", "inotify_rm_watch", "io_cancel", "io_destroy", "io_getevents",
+	"io_pgetevents", "io_setup", "io_submit", "io_uring_enter", "io_uring_register",
+	"io_uring_setup", "ioctl", "ioperm", "iopl", "ioprio_get", "ioprio_set", "kcmp",
+	"kexec_file_load", "kexec_load", "keyctl", "kill", "landlock_add_rule",
+	"landlock_create_ruleset", "landlock_restrict_self", "lchown", "lgetxattr", "link", "linkat",
+	"listen", "listmount", "listxattr", "llistxattr", "lookup_dcookie", "lremovexattr", "lseek",
+	"lsetxattr", "lsm_get_self_attr", "lsm_list_modules", "lsm_set_self_attr", "lstat", "madvise",
+	"map_shadow_stack", "mbind", "membarrier", "memfd_create", "memfd_secret", "migrate_pages",
+	"mincore", "mkdir", "mkdirat", "mknod", "mknodat", "mlock", "mlock2", "mlockall", "mmap",
+	"modify_ldt", "mount", "mount_setattr", "move_mount", "move_pages", "mprotect",
+	"mq_getsetattr", "mq_notify", "mq_open", "mq_timedreceive", "mq_timedsend", "mq_unlink",
+	"mremap", "mseal", "msgctl", "msgget", "msgrcv", "msgsnd", "msync", "munlock", "munlockall",
+	"munmap", "name_to_handle_at", "nanosleep", "newfstatat", "nfsservctl", "open",
+	"open_by_handle_at", "open_tree", "openat", "openat2", "pause", "perf_event_open",
+	"personality", "pidfd_getfd", "pidfd_open", "pidfd_send_signal", "pipe", "pipe2", "pivot_root",
+	"pkey_alloc", "pkey_free", "pkey_mprotect", "poll", "ppoll", "prctl", "pread64", "preadv",
+	"preadv2", "prlimit64", "process_madvise", "process_mrelease", "process_vm_readv",
+	"process_vm_writev", "pselect6", "ptrace", "putpmsg", "pwrite64", "pwritev", "pwritev2",
+	"query_module", "quotactl", "quotactl_fd", "read", "readahead", "readlink", "readlinkat",
+	"readv", "reboot", "recvfrom", "recvmmsg", "recvmsg", "remap_file_pages", "removexattr",
+	"rename", "renameat", "renameat2", "request_key", "restart_syscall", "rmdir", "rseq",
+	"rt_sigaction", "rt_sigpending", "rt_sigprocmask", "rt_sigqueueinfo", "rt_sigreturn",
+	"rt_sigsuspend", "rt_sigtimedwait", "rt_tgsigqueueinfo", "sched_get_priority_max",
+	"sched_get_priority_min", "sched_getaffinity", "sched_getattr", "sched_getparam",
+	"sched_getscheduler", "sched_rr_get_interval", "sched_setaffinity", "sched_setattr",
+	"sched_setparam", "sched_setscheduler", "sched_yield", "seccomp", "security", "select",
+	"semctl", "semget", "semop", "semtimedop", "sendfile", "sendmmsg", "sendmsg", "sendto",
+	"set_mempolicy", "set_mempolicy_home_node", "set_robust_list", "set_thread_area",
+	"set_tid_address", "setdomainname", "setfsgid", "setfsuid", "setgid", "setgroups",
+	"sethostname", "setitimer", "setns", "setpgid", "setpriority", "setregid", "setresgid",
+	"setresuid", "setreuid", "setrlimit", "setsid", "setsockopt", "settimeofday", "setuid",
+	"setxattr", "shmat", "shmctl", "shmdt", "shmget", "shutdown", "sigaltstack", "signalfd",
+	"signalfd4", "socket", "socketpair", "splice", "stat", "statfs", "statmount", "statx",
+	"swapoff", "swapon", "symlink", "symlinkat", "sync", "sync_file_range", "syncfs", "sysfs",
+	"sysinfo", "syslog", "tee", "tgkill", "time", "timer_create", "timer_delete",
+	"timer_getoverrun", "timer_gettime", "timer_settime", "timerfd_create", "timerfd_gettime",
+	"timerfd_settime", "times", "tkill", "truncate", "tuxcall", "umask", "umount2", "uname",
+	"unlink", "unlinkat", "unshare", "uretprobe", "uselib", "userfaultfd", "ustat", "utime",
+	"utimensat", "utimes", "vfork", "vhangup", "vmsplice", "vserver", "wait4", "waitid", "write",
+	"writev",
+];
+
+#[rustfmt::skip]
+const AARCH64: &[&str] = &[
+	"accept", "accept4", "acct", "add_key", "adjtimex", "bind", "bpf", "brk", "cachestat",
+	"capget", "capset", "chdir", "chroot", "clock_adjtime", "clock_getres", "clock_gettime",
+	"clock_nanosleep", "clock_settime", "clone", "clone3", "close", "close_range", "connect",
+	"copy_file_range", "delete_module", "dup", "dup3", "epoll_create1", "epoll_ctl", "epoll_pwait",
+	"epoll_pwait2", "eventfd2", "execve", "execveat", "exit", "exit_group", "faccessat",
+	"faccessat2", "fadvise64", "fallocate", "fanotify_init", "fanotify_mark", "fchdir", "fchmod",
+	"fchmodat", "fchmodat2", "fchown", "fchownat", "fcntl", "fdatasync", "fgetxattr",
+	"finit_module", "flistxattr", "flock", "fremovexattr", "fsconfig", "fsetxattr", "fsmount",
+	"fsopen", "fspick", "fstat", "fstatfs", "fsync", "ftruncate", "futex", "futex_requeue",
+	"futex_wait", "futex_waitv", "futex_wake", "get_mempolicy", "get_robust_list", "getcpu",
+	"getcwd", "getdents64", "getegid", "geteuid", "getgid", "getgroups", "getitimer",
+	"getpeername", "getpgid", "getpid", "getppid", "getpriority", "getrandom", "getresgid",
+	"getresuid", "getrlimit", "getrusage", "getsid", "getsockname", "getsockopt", "gettid",
+	"gettimeofday", "getuid", "getxattr", "init_module", "inotify_add_watch", "inotify_init1",
+	"inotify_rm_watch", "io_cancel", "io_destroy", "io_getevents", "io_pgetevents", "io_setup",
+	"io_submit", "io_uring_enter", "io_uring_register", "io_uring_setup", "ioctl", "ioprio_get",
+	"ioprio_set", "kcmp", "kexec_file_load", "kexec_load", "keyctl", "kill", "landlock_add_rule",
+	"landlock_create_ruleset", "landlock_restrict_self", "lgetxattr", "linkat", "listen",
+	"listmount", "listxattr", "llistxattr", "lookup_dcookie", "lremovexattr", "lseek", "lsetxattr",
+	"lsm_get_self_attr", "lsm_list_modules", "lsm_set_self_attr", "madvise", "map_shadow_stack",
+	"mbind", "membarrier", "memfd_create", "memfd_secret", "migrate_pages", "mincore", "mkdirat",
+	"mknodat", "mlock", "mlock2", "mlockall", "mmap", "mount", "mount_setattr", "move_mount",
+	"move_pages", "mprotect", "mq_getsetattr", "mq_notify", "mq_open", "mq_timedreceive",
+	"mq_timedsend", "mq_unlink", "mremap", "mseal", "msgctl", "msgget", "msgrcv", "msgsnd",
+	"msync", "munlock", "munlockall", "munmap", "name_to_handle_at", "nanosleep", "newfstatat",
+	"nfsservctl", "open_by_handle_at", "open_tree", "openat", "openat2", "perf_event_open",
+	"personality", "pidfd_getfd", "pidfd_open", "pidfd_send_signal", "pipe2", "pivot_root",
+	"pkey_alloc", "pkey_free", "pkey_mprotect", "ppoll", "prctl", "pread64", "preadv", "preadv2",
+	"prlimit64", "process_madvise", "process_mrelease", "process_vm_readv", "process_vm_writev",
+	"pselect6", "ptrace", "pwrite64", "pwritev", "pwritev2", "quotactl", "quotactl_fd", "read",
+	"readahead", "readlinkat", "readv", "reboot", "recvfrom", "recvmmsg", "recvmsg",
+	"remap_file_pages", "removexattr", "renameat", "renameat2", "request_key", "restart_syscall",
+	"rseq", "rt_sigaction", "rt_sigpending", "rt_sigprocmask", "rt_sigqueueinfo", "rt_sigreturn",
+	"rt_sigsuspend", "rt_sigtimedwait", "rt_tgsigqueueinfo", "sched_get_priority_max",
+	"sched_get_priority_min", "sched_getaffinity", "sched_getattr", "sched_getparam",
+	"sched_getscheduler", "sched_rr_get_interval", "sched_setaffinity", "sched_setattr",
+	"sched_setparam", "sched_setscheduler", "sched_yield", "seccomp", "semctl", "semget", "semop",
+	"semtimedop", "sendfile", "sendmmsg", "sendmsg", "sendto", "set_mempolicy",
+	"set_mempolicy_home_node", "set_robust_list", "set_tid_address", "setdomainname", "setfsgid",
+	"setfsuid", "setgid", "setgroups", "sethostname", "setitimer", "setns", "setpgid",
+	"setpriority", "setregid", "setresgid", "setresuid", "setreuid", "setrlimit", "setsid",
+	"setsockopt", "settimeofday", "setuid", "setxattr", "shmat", "shmctl", "shmdt", "shmget",
+	"shutdown", "sigaltstack", "signalfd4", "socket", "socketpair", "splice", "statfs",
+	"statmount", "statx", "swapoff", "swapon", "symlinkat", "sync", "sync_file_range", "syncfs",
+	"sysinfo", "syslog", "tee", "tgkill", "timer_create", "timer_delete", "timer_getoverrun",
+	"timer_gettime", "timer_settime", "timerfd_create", "timerfd_gettime", "timerfd_settime",
+	"times", "tkill", "truncate", "umask", "umount2", "uname", "unlinkat", "unshare",
+	"userfaultfd", "utimensat", "vhangup", "vmsplice", "wait4", "waitid", "write", "writev",
+];
+
+#[rustfmt::skip]
+const RISCV64: &[&str] = &[
+	"accept", "accept4", "acct", "add_key", "adjtimex", "bind", "bpf", "brk", "cachestat",
+	"capget", "capset", "chdir", "chroot", "clock_adjtime", "clock_getres", "clock_gettime",
+	"clock_nanosleep", "clock_settime", "clone", "clone3", "close", "close_range", "connect",
+	"copy_file_range", "delete_module", "dup", "dup3", "epoll_create1", "epoll_ctl", "epoll_pwait",
+	"epoll_pwait2", "eventfd2", "execve", "execveat", "exit", "exit_group", "faccessat",
+	"faccessat2", "fadvise64", "fallocate", "fanotify_init", "fanotify_mark", "fchdir", "fchmod",
+	"fchmodat", "fchmodat2", "fchown", "fchownat", "fcntl", "fdatasync", "fgetxattr",
+	"finit_module", "flistxattr", "flock", "fremovexattr", "fsconfig", "fsetxattr", "fsmount",
+	"fsopen", "fspick", "fstat", "fstatfs", "fsync", "ftruncate", "futex", "futex_requeue",
+	"futex_wait", "futex_waitv", "futex_wake", "get_mempolicy", "get_robust_list", "getcpu",
+	"getcwd", "getdents64", "getegid", "geteuid", "getgid", "getgroups", "getitimer",
+	"getpeername", "getpgid", "getpid", "getppid", "getpriority", "getrandom", "getresgid",
+	"getresuid", "getrlimit", "getrusage", "getsid", "getsockname", "getsockopt", "gettid",
+	"gettimeofday", "getuid", "getxattr", "init_module", "inotify_add_watch", "inotify_init1",
+	"inotify_rm_watch", "io_cancel", "io_destroy", "io_getevents", "io_pgetevents", "io_setup",
+	"io_submit", "io_uring_enter", "io_uring_register", "io_uring_setup", "ioctl", "ioprio_get",
+	"ioprio_set", "kcmp", "kexec_file_load", "kexec_load", "keyctl", "kill", "landlock_add_rule",
+	"landlock_create_ruleset", "landlock_restrict_self", "lgetxattr", "linkat", "listen",
+	"listmount", "listxattr", "llistxattr", "lookup_dcookie", "lremovexattr", "lseek", "lsetxattr",
+	"lsm_get_self_attr", "lsm_list_modules", "lsm_set_self_attr", "madvise", "map_shadow_stack",
+	"mbind", "membarrier", "memfd_create", "memfd_secret", "migrate_pages", "mincore", "mkdirat",
+	"mknodat", "mlock", "mlock2", "mlockall", "mmap", "mount", "mount_setattr", "move_mount",
+	"move_pages", "mprotect", "mq_getsetattr", "mq_notify", "mq_open", "mq_timedreceive",
+	"mq_timedsend", "mq_unlink", "mremap", "mseal", "msgctl", "msgget", "msgrcv", "msgsnd",
+	"msync", "munlock", "munlockall", "munmap", "name_to_handle_at", "nanosleep", "newfstatat",
+	"nfsservctl", "open_by_handle_at", "open_tree", "openat", "openat2", "perf_event_open",
+	"personality", "pidfd_getfd", "pidfd_open", "pidfd_send_signal", "pipe2", "pivot_root",
+	"pkey_alloc", "pkey_free", "pkey_mprotect", "ppoll", "prctl", "pread64", "preadv", "preadv2",
+	"prlimit64", "process_madvise", "process_mrelease", "process_vm_readv", "process_vm_writev",
+	"pselect6", "ptrace", "pwrite64", "pwritev", "pwritev2", "quotactl", "quotactl_fd", "read",
+	"readahead", "readlinkat", "readv", "reboot", "recvfrom", "recvmmsg", "recvmsg",
+	"remap_file_pages", "removexattr", "renameat2", "request_key", "restart_syscall",
+	"riscv_flush_icache", "riscv_hwprobe", "rseq", "rt_sigaction", "rt_sigpending",
+	"rt_sigprocmask", "rt_sigqueueinfo", "rt_sigreturn", "rt_sigsuspend", "rt_sigtimedwait",
+	"rt_tgsigqueueinfo", "sched_get_priority_max", "sched_get_priority_min", "sched_getaffinity",
+	"sched_getattr", "sched_getparam", "sched_getscheduler", "sched_rr_get_interval",
+	"sched_setaffinity", "sched_setattr", "sched_setparam", "sched_setscheduler", "sched_yield",
+	"seccomp", "semctl", "semget", "semop", "semtimedop", "sendfile", "sendmmsg", "sendmsg",
+	"sendto", "set_mempolicy", "set_mempolicy_home_node", "set_robust_list", "set_tid_address",
+	"setdomainname", "setfsgid", "setfsuid", "setgid", "setgroups", "sethostname", "setitimer",
+	"setns", "setpgid", "setpriority", "setregid", "setresgid", "setresuid", "setreuid",
+	"setrlimit", "setsid", "setsockopt", "settimeofday", "setuid", "setxattr", "shmat", "shmctl",
+	"shmdt", "shmget", "shutdown", "sigaltstack", "signalfd4", "socket", "socketpair", "splice",
+	"statfs", "statmount", "statx", "swapoff", "swapon", "symlinkat", "sync", "sync_file_range",
+	"syncfs", "sysinfo", "syslog", "tee", "tgkill", "timer_create", "timer_delete",
+	"timer_getoverrun", "timer_gettime", "timer_settime", "timerfd_create", "timerfd_gettime",
+	"timerfd_settime", "times", "tkill", "truncate", "umask", "umount2", "uname", "unlinkat",
+	"unshare", "userfaultfd", "utimensat", "vhangup", "vmsplice", "wait4", "waitid", "write",
+	"writev",
+];
+
+/// Checks whether seccompiler knows the syscall on the given architecture.
+pub fn is_known_syscall(name: &str, arch: TargetArch) -> bool {
+	let table = match arch {
+		TargetArch::x86_64 => X86_64,
+		TargetArch::aarch64 => AARCH64,
+		TargetArch::riscv64 => RISCV64,
+	};
+	table.binary_search(&name).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	#[test]
+	fn tables_are_sorted() {
+		for table in [X86_64, AARCH64, RISCV64] {
+			assert!(table.windows(2).all(|names| names[0] < names[1]));
+		}
+	}
+
+	#[test]
+	fn tables_match_seccompiler() {
+		for arch in [TargetArch::x86_64, TargetArch::aarch64, TargetArch::riscv64] {
+			let table = match arch {
+				TargetArch::x86_64 => X86_64,
+				TargetArch::aarch64 => AARCH64,
+				TargetArch::riscv64 => RISCV64,
+			};
+			let filter: Vec<_> = table
+				.iter()
+				.map(|name| json!({ "syscall": name }))
+				.collect();
+			let probe = json!({
+				"probe": { "mismatch_action": "allow", "match_action": "trap", "filter": filter }
+			});
+			if let Err(err) = seccompiler::compile_from_json(probe.to_string().as_bytes(), arch) {
+				panic!("Syscall table of {:?} is out of sync: {}", arch, err);
+			}
+		}
+	}
+
+	#[test]
+	fn known_syscalls() {
+		assert!(is_known_syscall("openat", TargetArch::x86_64));
+		assert!(is_known_syscall("arch_prctl", TargetArch::x86_64));
+		assert!(!is_known_syscall("arch_prctl", TargetArch::aarch64));
+		assert!(!is_known_syscall("_llseek", TargetArch::x86_64));
+		assert!(!is_known_syscall("", TargetArch::riscv64));
+	}
+}
@@ -0,0 +1,454 @@
+use crate::{cgroups, rootless};
+use oci_spec::runtime;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use zbus::zvariant::Value;
+
+/// A transient systemd scope unit, which contains all container processes.
+#[derive(Debug)]
+pub struct SystemdScope {
+	pub slice: String,
+	pub unit: String,
+}
+
+impl SystemdScope {
+	/// Parse `linux.cgroupsPath` in the form `slice:prefix:name` as used by the systemd cgroup driver.
+	pub fn from_spec(spec: &runtime::Spec, id: &str) -> Self {
+		let cgroups_path = spec
+			.linux()
+			.as_ref()
+			.and_then(|linux| linux.cgroups_path().as_ref())
+			.map(|path| path.to_str().unwrap().to_owned())
+			.unwrap_or_default();
+
+		// The systemd user instance of unprivileged users does not manage system.slice
+		let default_slice = if rootless::is_rootless() {
+			"user.slice"
+		} else {
+			"system.slice"
+		};
+
+		if cgroups_path.is_empty() {
+			return Self {
+				slice: String::from(default_slice),
+				unit: format!("runh-{id}.scope"),
+			};
+		}
+
+		let parts: Vec<&str> = cgroups_path.split(':').collect();
+		if parts.len() != 3 {
+			panic!(
+				"Expected cgroupsPath in the form slice:prefix:name for the systemd cgroup driver, got {}",
+				cgroups_path
+			);
+		}
+
+		let slice = if parts[0].is_empty() {
+			String::from(default_slice)
+		} else {
+			parts[0].to_owned()
+		};
+		let unit = if parts[1].is_empty() {
+			format!("{}.scope", parts[2])
+		} else {
+			format!("{}-{}.scope", parts[1], parts[2])
+		};
+		Self { slice, unit }
+	}
+
+	pub fn cgroup_path(&self) -> PathBuf {
+		PathBuf::from(cgroups::CGROUP_ROOT)
+			.join(expand_slice(&self.slice))
+			.join(&self.unit)
+	}
+}
+
+/// Expand a slice name into its cgroup path, e.g. `a-b.slice` into `a.slice/a-b.slice`.
+fn expand_slice(slice: &str) -> PathBuf {
+	if slice == "-.slice" {
+		return PathBuf::new();
+	}
+
+	let name = slice
+		.strip_suffix(".slice")
+		.unwrap_or_else(|| panic!("Invalid systemd slice name {}!", slice));
+	if name.is_empty()
+		|| name.starts_with('-')
+		|| name.ends_with('-')
+		|| name.contains("--")
+		|| name.contains('/')
+	{
+		panic!("Invalid systemd slice name {}!", slice);
+	}
+
+	let mut path = PathBuf::new();
+	let mut prefix = String::new();
+	for component in name.split('-') {
+		if !prefix.is_empty() {
+			prefix.push('-');
+		}
+		prefix.push_str(component);
+		path.push(format!("{prefix}.slice"));
+	}
+	path
+}
+
+/// The parts of the systemd manager API that are used by runh.
+trait SystemdManager {
+	fn start_transient_scope(&self, scope: &SystemdScope, pid: i32) -> Result<(), Box<dyn Error>>;
+	fn stop_unit(&self, unit: &str) -> Result<(), Box<dyn Error>>;
+}
+
+struct DbusSystemdManager {
+	connection: zbus::blocking::Connection,
+}
+
+impl DbusSystemdManager {
+	fn new() -> Result<Self, Box<dyn Error>> {
+		let connection = if rootless::is_rootless() {
+			zbus::blocking::Connection::session()?
+		} else {
+			zbus::blocking::Connection::system()?
+		};
+		Ok(Self { connection })
+	}
+
+	fn proxy(&self) -> Result<zbus::blocking::Proxy<'_>, Box<dyn Error>> {
+		Ok(zbus::blocking::Proxy::new(
+			&self.connection,
+			"org.freedesktop.systemd1",
+			"/org/freedesktop/systemd1",
+			"org.freedesktop.systemd1.Manager",
+		)?)
+	}
+}
+
+/// Properties of the transient scope unit. Resource limits are applied by runh itself,
+/// systemd only has to delegate the cgroup.
+fn get_scope_properties(scope: &SystemdScope, pid: i32) -> Vec<(&'static str, Value<'static>)> {
+	vec![
+		(
+			"Description",
+			Value::from(format!("runh container {}", scope.unit)),
+		),
+		("Slice", Value::from(scope.slice.clone())),
+		("PIDs", Value::from(vec![pid as u32])),
+		("Delegate", Value::from(true)),
+		("DefaultDependencies", Value::from(false)),
+		("MemoryAccounting", Value::from(true)),
+		("CPUAccounting", Value::from(true)),
+		("TasksAccounting", Value::from(true)),
+		("IOAccounting", Value::from(true)),
+	]
+}
+
+impl SystemdManager for DbusSystemdManager {
+	fn start_transient_scope(&self, scope: &SystemdScope, pid: i32) -> Result<(), Box<dyn Error>> {
+		let properties = get_scope_properties(scope, pid);
+		let aux: Vec<(&str, Vec<(&str, Value)>)> = Vec::new();
+
+		self.proxy()?.call_method(
+			"StartTransientUnit",
+			&(scope.unit.as_str(), "replace", properties, aux),
+		)?;
+		Ok(())
+	}
+
+	fn stop_unit(&self, unit: &str) -> Result<(), Box<dyn Error>> {
+		self.proxy()?.call_method("StopUnit", &(unit, "replace"))?;
+		Ok(())
+	}
+}
+
+fn get_manager() -> Result<Box<dyn SystemdManager>, Box<dyn Error>> {
+	Ok(Box::new(DbusSystemdManager::new()?))
+}
+
+/// Start a transient scope for the container process and return the path of its cgroup.
+pub fn start_scope(spec: &runtime::Spec, id: &str, pid: i32) -> PathBuf {
+	let manager =
+		get_manager().unwrap_or_else(|err| panic!("Could not connect to systemd: {}", err));
+	start_scope_with(manager.as_ref(), spec, id, pid, Path::new("/proc"))
+}
+
+fn start_scope_with(
+	manager: &dyn SystemdManager,
+	spec: &runtime::Spec,
+	id: &str,
+	pid: i32,
+	proc_dir: &Path,
+) -> PathBuf {
+	let scope = SystemdScope::from_spec(spec, id);
+	debug!(
+		"Starting systemd scope {:?} at {:?} for process {}",
+		scope,
+		scope.cgroup_path(),
+		pid
+	);
+
+	manager
+		.start_transient_scope(&scope, pid)
+		.unwrap_or_else(|err| panic!("Could not start systemd scope {}: {}", scope.unit, err));
+
+	// StartTransientUnit only queues a job, so wait until the process has been moved.
+	// The cgroup is read from procfs, as the user instance of systemd manages a subtree.
+	let cgroup_file = proc_dir.join(pid.to_string()).join("cgroup");
+	for _ in 0..50 {
+		let cgroup = std::fs::read_to_string(&cgroup_file)
+			.unwrap_or_default()
+			.lines()
+			.find_map(|line| line.strip_prefix("0::").map(PathBuf::from));
+		if let Some(cgroup) = cgroup.filter(|cgroup| cgroup.ends_with(&scope.unit)) {
+			return PathBuf::from(cgroups::CGROUP_ROOT).join(cgroup.strip_prefix("/").unwrap());
+		}
+		std::thread::sleep(Duration::from_millis(100));
+	}
+	panic!(
+		"Process {} was not moved into the cgroup of systemd scope {}!",
+		pid, scope.unit
+	);
+}
+
+/// Checks whether the cgroup belongs to a scope unit created by the systemd cgroup driver.
+pub fn is_scope(cgroup: &Path) -> bool {
+	cgroup
+		.extension()
+		.map(|extension| extension == "scope")
+		.unwrap_or(false)
+}
+
+/// Stop the scope unit that owns the given cgroup.
+pub fn stop_scope(cgroup: &Path) {
+	match get_manager() {
+		Ok(manager) => stop_scope_with(manager.as_ref(), cgroup),
+		Err(err) => warn!("Could not connect to systemd: {}", err),
+	}
+}
+
+fn stop_scope_with(manager: &dyn SystemdManager, cgroup: &Path) {
+	let unit = cgroup.file_name().unwrap().to_str().unwrap();
+	debug!("Stopping systemd scope {}", unit);
+	if let Err(err) = manager.stop_unit(unit) {
+		warn!("Could not stop systemd scope {}: {}", unit, err);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::cell::RefCell;
+
+	/// Records the calls instead of talking to systemd. If `proc_dir` is set, started
+	/// processes are moved into the cgroup of their scope below `cgroup_root` after a short delay.
+	#[derive(Default)]
+	struct FakeSystemdManager {
+		started: RefCell<Vec<(String, String, i32)>>,
+		stopped: RefCell<Vec<String>>,
+		proc_dir: Option<PathBuf>,
+		cgroup_root: PathBuf,
+	}
+
+	impl SystemdManager for FakeSystemdManager {
+		fn start_transient_scope(
+			&self,
+			scope: &SystemdScope,
+			pid: i32,
+		) -> Result<(), Box<dyn Error>> {
+			self.started
+				.borrow_mut()
+				.push((scope.slice.clone(), scope.unit.clone(), pid));
+			if let Some(proc_dir) = &self.proc_dir {
+				let cgroup_file = proc_dir.join(pid.to_string()).join("cgroup");
+				let cgroup = PathBuf::from("/")
+					.join(&self.cgroup_root)
+					.join(expand_slice(&scope.slice))
+					.join(&scope.unit);
+				std::thread::spawn(move || {
+					std::thread::sleep(Duration::from_millis(200));
+					std::fs::write(cgroup_file, format!("0::{}\n", cgroup.display())).unwrap();
+				});
+			}
+			Ok(())
+		}
+
+		fn stop_unit(&self, unit: &str) -> Result<(), Box<dyn Error>> {
+			self.stopped.borrow_mut().push(unit.to_owned());
+			Ok(())
+		}
+	}
+
+	/// A fake procfs, in which the process is still in the cgroup of the caller.
+	fn fake_proc_dir(name: &str, pid: i32) -> PathBuf {
+		let proc_dir =
+			std::env::temp_dir().join(format!("runh-systemd-{}-{}", std::process::id(), name));
+		let _ = std::fs::remove_dir_all(&proc_dir);
+		std::fs::create_dir_all(proc_dir.join(pid.to_string())).unwrap();
+		std::fs::write(
+			proc_dir.join(pid.to_string()).join("cgroup"),
+			"0::/user.slice/session-1.scope\n",
+		)
+		.unwrap();
+		proc_dir
+	}
+
+	fn spec_with_cgroups_path(path: Option<&str>) -> runtime::Spec {
+		let mut linux = runtime::Linux::default();
+		linux.set_cgroups_path(path.map(PathBuf::from));
+		let mut spec = runtime::Spec::default();
+		spec.set_linux(Some(linux));
+		spec
+	}
+
+	fn default_slice() -> &'static str {
+		if rootless::is_rootless() {
+			"user.slice"
+		} else {
+			"system.slice"
+		}
+	}
+
+	#[test]
+	fn scope_from_spec_without_cgroups_path() {
+		let scope = SystemdScope::from_spec(&spec_with_cgroups_path(None), "ctr");
+		assert_eq!(scope.slice, default_slice());
+		assert_eq!(scope.unit, "runh-ctr.scope");
+	}
+
+	#[test]
+	fn scope_from_spec_with_cgroups_path() {
+		let spec = spec_with_cgroups_path(Some("machine.slice:docker:abc"));
+		let scope = SystemdScope::from_spec(&spec, "ctr");
+		assert_eq!(scope.slice, "machine.slice");
+		assert_eq!(scope.unit, "docker-abc.scope");
+
+		let scope = SystemdScope::from_spec(&spec_with_cgroups_path(Some("::abc")), "ctr");
+		assert_eq!(scope.slice, default_slice());
+		assert_eq!(scope.unit, "abc.scope");
+	}
+
+	#[test]
+	#[should_panic(expected = "slice:prefix:name")]
+	fn scope_from_spec_rejects_cgroupfs_path() {
+		SystemdScope::from_spec(&spec_with_cgroups_path(Some("/runh/ctr")), "ctr");
+	}
+
+	#[test]
+	fn scope_cgroup_path() {
+		let scope = SystemdScope {
+			slice: String::from("a-b.slice"),
+			unit: String::from("runh-ctr.scope"),
+		};
+		assert_eq!(
+			scope.cgroup_path(),
+			PathBuf::from(cgroups::CGROUP_ROOT).join("a.slice/a-b.slice/runh-ctr.scope")
+		);
+	}
+
+	#[test]
+	fn expand_nested_slice() {
+		assert_eq!(
+			expand_slice("a-b-c.slice"),
+			PathBuf::from("a.slice/a-b.slice/a-b-c.slice")
+		);
+		assert_eq!(expand_slice("system.slice"), PathBuf::from("system.slice"));
+		assert_eq!(expand_slice("-.slice"), PathBuf::new());
+	}
+
+	#[test]
+	fn expand_invalid_slices() {
+		for slice in [
+			"a-.slice",
+			"-a.slice",
+			"a--b.slice",
+			".slice",
+			"a/b.slice",
+			"system",
+		] {
+			assert!(
+				std::panic::catch_unwind(|| expand_slice(slice)).is_err(),
+				"{} should be rejected",
+				slice
+			);
+		}
+	}
+
+	#[test]
+	fn scope_properties() {
+		let scope = SystemdScope {
+			slice: String::from("system.slice"),
+			unit: String::from("runh-ctr.scope"),
+		};
+		let properties = get_scope_properties(&scope, 42);
+		let get = |name: &str| {
+			properties
+				.iter()
+				.find(|(key, _)| *key == name)
+				.map(|(_, value)| value)
+				.unwrap_or_else(|| panic!("Missing property {}", name))
+		};
+
+		assert_eq!(
+			*get("Description"),
+			Value::from("runh container runh-ctr.scope")
+		);
+		assert_eq!(*get("Slice"), Value::from("system.slice"));
+		assert_eq!(*get("PIDs"), Value::from(vec![42u32]));
+		assert_eq!(*get("Delegate"), Value::from(true));
+		assert_eq!(*get("DefaultDependencies"), Value::from(false));
+	}
+
+	#[test]
+	fn start_scope_waits_for_process() {
+		let proc_dir = fake_proc_dir("start", 7);
+		let manager = FakeSystemdManager {
+			proc_dir: Some(proc_dir.clone()),
+			..Default::default()
+		};
+		let spec = spec_with_cgroups_path(Some("machine-runh.slice:runh:ctr"));
+		let cgroup = start_scope_with(&manager, &spec, "ctr", 7, &proc_dir);
+		std::fs::remove_dir_all(&proc_dir).unwrap();
+
+		assert_eq!(
+			*manager.started.borrow(),
+			vec![(
+				String::from("machine-runh.slice"),
+				String::from("runh-ctr.scope"),
+				7
+			)]
+		);
+		assert_eq!(
+			cgroup,
+			PathBuf::from(cgroups::CGROUP_ROOT)
+				.join("machine.slice/machine-runh.slice/runh-ctr.scope")
+		);
+
+		stop_scope_with(&manager, &cgroup);
+		assert_eq!(
+			*manager.stopped.borrow(),
+			vec![String::from("runh-ctr.scope")]
+		);
+	}
+
+	#[test]
+	fn start_scope_in_default_slice_of_user_instance() {
+		let proc_dir = fake_proc_dir("user", 8);
+		let user_instance = PathBuf::from("user.slice/user-1000.slice/user@1000.service");
+		let manager = FakeSystemdManager {
+			proc_dir: Some(proc_dir.clone()),
+			cgroup_root: user_instance.clone(),
+			..Default::default()
+		};
+		let cgroup = start_scope_with(&manager, &spec_with_cgroups_path(None), "ctr", 8, &proc_dir);
+		std::fs::remove_dir_all(&proc_dir).unwrap();
+
+		assert_eq!(manager.started.borrow()[0].0, default_slice());
+		assert_eq!(
+			cgroup,
+			PathBuf::from(cgroups::CGROUP_ROOT)
+				.join(user_instance)
+				.join(default_slice())
+				.join("runh-ctr.scope")
+		);
+	}
+}
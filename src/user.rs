@@ -0,0 +1,37 @@
+use crate::namespaces;
+use nix::sys::stat::Mode;
+use nix::unistd::{Gid, Uid};
+use oci_spec::runtime;
+
+/// Switch to the user, groups and umask of the container process.
+/// The supplementary groups have to be set while the process is still privileged.
+pub fn switch_user(user: &runtime::User) {
+	debug!(
+		"Switch to user with uid {}, gid {}, additional gids {:?}",
+		user.uid(),
+		user.gid(),
+		user.additional_gids()
+	);
+
+	let additional_gids: Vec<Gid> = user
+		.additional_gids()
+		.as_ref()
+		.map(|gids| gids.iter().map(|gid| Gid::from_raw(*gid)).collect())
+		.unwrap_or_default();
+	if namespaces::is_setgroups_allowed() {
+		nix::unistd::setgroups(&additional_gids).expect("Could not set additional groups!");
+	} else if !additional_gids.is_empty() {
+		warn!("setgroups is denied in the user namespace, ignoring additional groups!");
+	}
+
+	let gid = Gid::from_raw(user.gid());
+	nix::unistd::setresgid(gid, gid, gid)
+		.unwrap_or_else(|err| panic!("Could not set group ID to {}: {}", gid, err));
+	let uid = Uid::from_raw(user.uid());
+	nix::unistd::setresuid(uid, uid, uid)
+		.unwrap_or_else(|err| panic!("Could not set user ID to {}: {}", uid, err));
+
+	if let Some(umask) = user.umask() {
+		let _ = nix::sys::stat::umask(Mode::from_bits_truncate(umask as libc::mode_t));
+	}
+}
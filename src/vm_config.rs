@@ -0,0 +1,353 @@
+use crate::consts;
+use crate::hermit::{self, VmResources};
+use oci_spec::runtime::Spec;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Network of a hermit VM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkMode {
+	/// A macvtap device in the network namespace of the container
+	Tap,
+	/// QEMU user networking, optionally forwarding a TCP port to the guest
+	User(Option<u16>),
+	None,
+}
+
+/// Hypervisor that runs a hermit application
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmmKind {
+	Qemu,
+	Uhyve,
+	CloudHypervisor,
+}
+
+/// Per-container VM options of a hermit container
+#[derive(Debug)]
+pub struct VmConfig {
+	pub vmm: VmmKind,
+	pub resources: VmResources,
+	pub micro_vm: bool,
+	/// Use KVM. Detected inside the container if not set.
+	pub kvm: Option<bool>,
+	pub network: NetworkMode,
+	pub extra_kernel_args: Vec<String>,
+	/// Time the guest may take to power down on SIGTERM before the VMM is stopped
+	pub shutdown_grace_period: Duration,
+}
+
+fn parse_annotation<T: FromStr>(
+	annotations: &HashMap<String, String>,
+	key: &str,
+) -> Result<Option<T>, String> {
+	annotations
+		.get(key)
+		.map(|value| {
+			value
+				.trim()
+				.parse::<T>()
+				.map_err(|_| format!("Invalid value {value:?} for annotation {key}"))
+		})
+		.transpose()
+}
+
+impl VmConfig {
+	/// Parse and validate the VM options from the annotations of the spec.
+	pub fn from_spec(spec: &Spec) -> Result<Self, String> {
+		let empty = HashMap::new();
+		let annotations = spec.annotations().as_ref().unwrap_or(&empty);
+
+		let vmm = match annotations
+			.get(consts::ANNOTATION_VM_VMM)
+			.map(|vmm| vmm.trim())
+		{
+			None | Some("qemu") => VmmKind::Qemu,
+			Some("uhyve") => VmmKind::Uhyve,
+			Some("cloud-hypervisor") => VmmKind::CloudHypervisor,
+			Some(vmm) => {
+				return Err(format!(
+					"Invalid value {:?} for annotation {}, expected qemu, uhyve or cloud-hypervisor",
+					vmm,
+					consts::ANNOTATION_VM_VMM
+				))
+			}
+		};
+
+		let micro_vm =
+			parse_annotation::<bool>(annotations, consts::ANNOTATION_VM_MICROVM)?.unwrap_or(false);
+
+		let memory_overhead =
+			parse_annotation::<u64>(annotations, consts::ANNOTATION_VMM_MEMORY_OVERHEAD)?;
+		let mut resources = hermit::get_vm_resources(spec, memory_overhead)?;
+		if let Some(cpus) = parse_annotation::<u32>(annotations, consts::ANNOTATION_VM_SMP)? {
+			if cpus == 0 {
+				return Err(format!(
+					"Annotation {} requires at least one vCPU",
+					consts::ANNOTATION_VM_SMP
+				));
+			}
+			resources.cpus = cpus;
+		}
+		if let Some(memory) = parse_annotation::<u64>(annotations, consts::ANNOTATION_VM_MEMORY)? {
+			if memory == 0 {
+				return Err(format!(
+					"Annotation {} requires a memory size in MiB",
+					consts::ANNOTATION_VM_MEMORY
+				));
+			}
+			if let Some(max_memory) = resources.max_memory.filter(|max| memory > *max) {
+				return Err(format!(
+					"Annotation {} requests {} MiB, but the memory limit leaves only {} MiB for the VM",
+					consts::ANNOTATION_VM_MEMORY,
+					memory,
+					max_memory
+				));
+			}
+			resources.memory = memory;
+		}
+
+		let kvm = parse_annotation::<bool>(annotations, consts::ANNOTATION_VM_KVM)?;
+
+		let user_port = parse_annotation::<u16>(annotations, consts::ANNOTATION_VM_USER_PORT)?
+			.filter(|user_port| *user_port > 0);
+		let network = match annotations
+			.get(consts::ANNOTATION_VM_NETMODE)
+			.map(|netmode| netmode.trim())
+		{
+			None if vmm == VmmKind::Uhyve => NetworkMode::None,
+			None if user_port.is_some() => NetworkMode::User(user_port),
+			None | Some("tap") => NetworkMode::Tap,
+			Some("user") => NetworkMode::User(user_port),
+			Some("none") => NetworkMode::None,
+			Some(netmode) => {
+				return Err(format!(
+					"Invalid value {:?} for annotation {}, expected tap, user or none",
+					netmode,
+					consts::ANNOTATION_VM_NETMODE
+				))
+			}
+		};
+		if user_port.is_some() && !matches!(network, NetworkMode::User(_)) {
+			return Err(format!(
+				"Annotation {} requires the user network mode",
+				consts::ANNOTATION_VM_USER_PORT
+			));
+		}
+
+		let extra_kernel_args: Vec<String> = annotations
+			.get(consts::ANNOTATION_VM_EXTRA_KERNEL_ARGS)
+			.map(|args| args.split_whitespace().map(String::from).collect())
+			.unwrap_or_default();
+
+		let shutdown_grace_period = Duration::from_secs(
+			parse_annotation::<u64>(annotations, consts::ANNOTATION_SHUTDOWN_GRACE_PERIOD)?
+				.unwrap_or(consts::DEFAULT_SHUTDOWN_GRACE_PERIOD),
+		);
+
+		if vmm == VmmKind::Uhyve {
+			if network != NetworkMode::None {
+				return Err(String::from("uhyve does not support networking"));
+			}
+			if kvm == Some(false) {
+				return Err(String::from("uhyve requires KVM"));
+			}
+			if !extra_kernel_args.is_empty() {
+				return Err(format!(
+					"Annotation {} is not supported by uhyve",
+					consts::ANNOTATION_VM_EXTRA_KERNEL_ARGS
+				));
+			}
+		}
+
+		if vmm == VmmKind::CloudHypervisor {
+			if matches!(network, NetworkMode::User(_)) {
+				return Err(String::from(
+					"cloud-hypervisor does not support the user network mode",
+				));
+			}
+			if kvm == Some(false) {
+				return Err(String::from("cloud-hypervisor requires KVM"));
+			}
+		}
+
+		Ok(Self {
+			vmm,
+			resources,
+			micro_vm,
+			kvm,
+			network,
+			extra_kernel_args,
+			shutdown_grace_period,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use oci_spec::runtime::{Linux, LinuxMemoryBuilder, LinuxResources};
+
+	fn spec_with_annotations(annotations: &[(&str, &str)]) -> Spec {
+		let mut spec = Spec::default();
+		spec.set_annotations(Some(
+			annotations
+				.iter()
+				.map(|(key, value)| (key.to_string(), value.to_string()))
+				.collect(),
+		));
+		spec
+	}
+
+	fn spec_with_memory_limit(annotations: &[(&str, &str)], limit: i64) -> Spec {
+		let mut spec = spec_with_annotations(annotations);
+		let mut resources = LinuxResources::default();
+		resources.set_memory(Some(
+			LinuxMemoryBuilder::default().limit(limit).build().unwrap(),
+		));
+		let mut linux = Linux::default();
+		linux.set_resources(Some(resources));
+		spec.set_linux(Some(linux));
+		spec
+	}
+
+	#[test]
+	fn defaults_without_annotations() {
+		let config = VmConfig::from_spec(&spec_with_annotations(&[])).unwrap();
+		assert_eq!(config.vmm, VmmKind::Qemu);
+		assert!(!config.micro_vm);
+		assert_eq!(config.kvm, None);
+		assert_eq!(config.network, NetworkMode::Tap);
+		assert!(config.extra_kernel_args.is_empty());
+		assert_eq!(
+			config.shutdown_grace_period,
+			Duration::from_secs(consts::DEFAULT_SHUTDOWN_GRACE_PERIOD)
+		);
+	}
+
+	#[test]
+	fn parses_all_annotations() {
+		let config = VmConfig::from_spec(&spec_with_annotations(&[
+			(consts::ANNOTATION_VM_VMM, "cloud-hypervisor"),
+			(consts::ANNOTATION_VM_MICROVM, "true"),
+			(consts::ANNOTATION_VM_SMP, "4"),
+			(consts::ANNOTATION_VM_MEMORY, "256"),
+			(consts::ANNOTATION_VM_KVM, "true"),
+			(consts::ANNOTATION_VM_NETMODE, " none "),
+			(consts::ANNOTATION_VM_EXTRA_KERNEL_ARGS, "-freq 800  -v"),
+			(consts::ANNOTATION_SHUTDOWN_GRACE_PERIOD, "3"),
+		]))
+		.unwrap();
+		assert_eq!(config.vmm, VmmKind::CloudHypervisor);
+		assert!(config.micro_vm);
+		assert_eq!(config.resources.cpus, 4);
+		assert_eq!(config.resources.memory, 256);
+		assert_eq!(config.kvm, Some(true));
+		assert_eq!(config.network, NetworkMode::None);
+		assert_eq!(config.extra_kernel_args, ["-freq", "800", "-v"]);
+		assert_eq!(config.shutdown_grace_period, Duration::from_secs(3));
+	}
+
+	#[test]
+	fn user_port_selects_user_network() {
+		let config = VmConfig::from_spec(&spec_with_annotations(&[(
+			consts::ANNOTATION_VM_USER_PORT,
+			"8080",
+		)]))
+		.unwrap();
+		assert_eq!(config.network, NetworkMode::User(Some(8080)));
+
+		assert!(VmConfig::from_spec(&spec_with_annotations(&[
+			(consts::ANNOTATION_VM_USER_PORT, "8080"),
+			(consts::ANNOTATION_VM_NETMODE, "tap"),
+		]))
+		.is_err());
+	}
+
+	#[test]
+	fn rejects_malformed_annotations() {
+		for (key, value) in [
+			(consts::ANNOTATION_VM_VMM, "xen"),
+			(consts::ANNOTATION_VM_MICROVM, "1"),
+			(consts::ANNOTATION_VM_SMP, "0"),
+			(consts::ANNOTATION_VM_SMP, "two"),
+			(consts::ANNOTATION_VM_MEMORY, "0"),
+			(consts::ANNOTATION_VM_MEMORY, "1G"),
+			(consts::ANNOTATION_VM_KVM, "yes"),
+			(consts::ANNOTATION_VM_NETMODE, "bridge"),
+			(consts::ANNOTATION_VM_USER_PORT, "70000"),
+			(consts::ANNOTATION_VMM_MEMORY_OVERHEAD, "-1"),
+			(consts::ANNOTATION_SHUTDOWN_GRACE_PERIOD, "10s"),
+		] {
+			assert!(
+				VmConfig::from_spec(&spec_with_annotations(&[(key, value)])).is_err(),
+				"{}={} was accepted",
+				key,
+				value
+			);
+		}
+	}
+
+	#[test]
+	fn ignores_legacy_process_env() {
+		let mut spec = spec_with_annotations(&[]);
+		let mut process = oci_spec::runtime::Process::default();
+		process.set_env(Some(vec![
+			String::from("RUNH_MICRO_VM=1"),
+			String::from("RUNH_USER_PORT=8080"),
+		]));
+		spec.set_process(Some(process));
+		let config = VmConfig::from_spec(&spec).unwrap();
+		assert!(!config.micro_vm);
+		assert_eq!(config.network, NetworkMode::Tap);
+	}
+
+	#[test]
+	fn rejects_unsupported_vmm_options() {
+		let uhyve = (consts::ANNOTATION_VM_VMM, "uhyve");
+		let config = VmConfig::from_spec(&spec_with_annotations(&[uhyve])).unwrap();
+		assert_eq!(config.network, NetworkMode::None);
+		for option in [
+			(consts::ANNOTATION_VM_NETMODE, "tap"),
+			(consts::ANNOTATION_VM_KVM, "false"),
+			(consts::ANNOTATION_VM_EXTRA_KERNEL_ARGS, "-v"),
+		] {
+			assert!(VmConfig::from_spec(&spec_with_annotations(&[uhyve, option])).is_err());
+		}
+
+		let cloud_hypervisor = (consts::ANNOTATION_VM_VMM, "cloud-hypervisor");
+		for option in [
+			(consts::ANNOTATION_VM_NETMODE, "user"),
+			(consts::ANNOTATION_VM_KVM, "false"),
+		] {
+			assert!(
+				VmConfig::from_spec(&spec_with_annotations(&[cloud_hypervisor, option])).is_err()
+			);
+		}
+	}
+
+	#[test]
+	fn memory_annotation_has_to_fit_into_the_limit() {
+		let limit = 512 * 1024 * 1024;
+		let config = VmConfig::from_spec(&spec_with_memory_limit(
+			&[(consts::ANNOTATION_VM_MEMORY, "256")],
+			limit,
+		))
+		.unwrap();
+		assert_eq!(config.resources.memory, 256);
+
+		assert!(VmConfig::from_spec(&spec_with_memory_limit(
+			&[(consts::ANNOTATION_VM_MEMORY, "512")],
+			limit,
+		))
+		.is_err());
+		assert!(VmConfig::from_spec(&spec_with_memory_limit(
+			&[
+				(consts::ANNOTATION_VM_MEMORY, "448"),
+				(consts::ANNOTATION_VMM_MEMORY_OVERHEAD, "64"),
+			],
+			limit,
+		))
+		.is_ok());
+	}
+}